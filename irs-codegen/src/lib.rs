@@ -0,0 +1,284 @@
+//! Generates `irs_rs::msg::params::data` from a TOML parameter spec, see
+//! `spec/params.toml` for the format.
+
+use std::{collections::BTreeSet, fmt, fmt::Write};
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Spec {
+    #[serde(rename = "param", default)]
+    pub params: Vec<ParamSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParamSpec {
+    pub id: u16,
+    pub name: String,
+    #[serde(rename = "struct")]
+    pub struct_name: Option<String>,
+    pub response_to: Option<u16>,
+    pub doc: Option<String>,
+    #[serde(rename = "field", default)]
+    pub fields: Vec<FieldSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldSpec {
+    pub name: String,
+    pub width: u8,
+    #[serde(default)]
+    pub signed: bool,
+    pub unit: Option<String>,
+    pub doc: Option<String>,
+}
+
+impl ParamSpec {
+    pub fn struct_name(&self) -> String {
+        self.struct_name
+            .clone()
+            .unwrap_or_else(|| format!("Param{}", self.id))
+    }
+}
+
+impl FieldSpec {
+    fn rust_type(&self) -> String {
+        format!("{}{}", if self.signed { "i" } else { "u" }, self.width)
+    }
+}
+
+#[derive(Debug)]
+pub enum CodegenError {
+    Parse(toml::de::Error),
+    Invalid(String),
+}
+
+impl std::error::Error for CodegenError {}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodegenError::Parse(e) => write!(f, "bad spec: {e}"),
+            CodegenError::Invalid(msg) => write!(f, "invalid spec: {msg}"),
+        }
+    }
+}
+
+impl From<toml::de::Error> for CodegenError {
+    fn from(e: toml::de::Error) -> Self {
+        CodegenError::Parse(e)
+    }
+}
+
+impl Spec {
+    pub fn parse(src: &str) -> Result<Self, CodegenError> {
+        let spec: Spec = toml::from_str(src)?;
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    fn validate(&self) -> Result<(), CodegenError> {
+        let invalid = |msg: String| Err(CodegenError::Invalid(msg));
+        let ids: BTreeSet<u16> = self.params.iter().map(|p| p.id).collect();
+        if ids.len() != self.params.len() {
+            return invalid("duplicate parameter id".into());
+        }
+        let mut names = BTreeSet::new();
+        for param in &self.params {
+            for name in [&param.name, &param.struct_name()] {
+                if !is_ident(name) {
+                    return invalid(format!("{name:?} is not an identifier"));
+                }
+            }
+            if !names.insert(&param.name) {
+                return invalid(format!("duplicate parameter name {}", param.name));
+            }
+            if let Some(req) = param.response_to
+                && !ids.contains(&req)
+            {
+                return invalid(format!("{} answers unknown id {req}", param.name));
+            }
+            let mut fields = BTreeSet::new();
+            for field in &param.fields {
+                if !is_ident(&field.name) || !fields.insert(&field.name) {
+                    return invalid(format!("{}: bad field name {:?}", param.name, field.name));
+                }
+                if ![8, 16, 32, 64].contains(&field.width) {
+                    return invalid(format!(
+                        "{}.{}: width must be 8, 16, 32 or 64",
+                        param.name, field.name
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Rust source of the `params::data` module for `spec`, with CRLF line
+/// endings like the other `msg` modules.
+pub fn generate(spec: &Spec, spec_path: &str) -> String {
+    let mut out = String::new();
+    // writing to a String cannot fail
+    let _ = write_module(&mut out, spec, spec_path);
+    out.replace('\n', "\r\n")
+}
+
+fn write_module(out: &mut String, spec: &Spec, spec_path: &str) -> fmt::Result {
+    writeln!(
+        out,
+        "// @generated by irs-codegen from {spec_path}, do not edit."
+    )?;
+    writeln!(out, "// Regenerate with `cargo run -p irs-codegen`.")?;
+    writeln!(out)?;
+    writeln!(out, "use serde::{{Deserialize, Serialize}};")?;
+    writeln!(out)?;
+    writeln!(out, "use super::IrsParam;")?;
+    writeln!(out)?;
+
+    writeln!(out, "#[repr(u16)]")?;
+    writeln!(
+        out,
+        "#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]"
+    )?;
+    writeln!(out, "pub enum ParamId {{")?;
+    for param in &spec.params {
+        writeln!(out, "    {} = {},", param.name, param.id)?;
+    }
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+    writeln!(out, "impl TryFrom<u16> for ParamId {{")?;
+    writeln!(out, "    type Error = ();")?;
+    writeln!(
+        out,
+        "    fn try_from(value: u16) -> Result<Self, Self::Error> {{"
+    )?;
+    writeln!(out, "        match value {{")?;
+    for param in &spec.params {
+        writeln!(
+            out,
+            "            {} => Ok(ParamId::{}),",
+            param.id, param.name
+        )?;
+    }
+    writeln!(out, "            _ => Err(()),")?;
+    writeln!(out, "        }}")?;
+    writeln!(out, "    }}")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+    writeln!(out, "impl From<ParamId> for u16 {{")?;
+    writeln!(out, "    fn from(id: ParamId) -> Self {{")?;
+    writeln!(out, "        id as u16")?;
+    writeln!(out, "    }}")?;
+    writeln!(out, "}}")?;
+
+    for param in &spec.params {
+        writeln!(out)?;
+        write_param(out, param)?;
+    }
+    Ok(())
+}
+
+fn write_param(out: &mut String, param: &ParamSpec) -> fmt::Result {
+    if let Some(doc) = &param.doc {
+        writeln!(out, "/// {doc}")?;
+    }
+    writeln!(
+        out,
+        "#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, IrsParam)]"
+    )?;
+    write!(out, "#[irs(id = {}", param.id)?;
+    if let Some(req) = param.response_to {
+        write!(out, ", response_to = {req}")?;
+    }
+    writeln!(out, ", name = {:?})]", param.name)?;
+    let struct_name = param.struct_name();
+    if param.fields.is_empty() {
+        return writeln!(out, "pub struct {struct_name};");
+    }
+    writeln!(out, "pub struct {struct_name} {{")?;
+    for field in &param.fields {
+        if let Some(doc) = &field.doc {
+            writeln!(out, "    /// {doc}")?;
+        }
+        if let Some(unit) = &field.unit {
+            writeln!(out, "    /// Unit: {unit}.")?;
+        }
+        writeln!(out, "    pub {}: {},", field.name, field.rust_type())?;
+    }
+    writeln!(out, "}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC_PATH: &str = "spec/params.toml";
+
+    fn workspace_file(path: &str) -> String {
+        let root = concat!(env!("CARGO_MANIFEST_DIR"), "/..");
+        std::fs::read_to_string(format!("{root}/{path}")).unwrap()
+    }
+
+    #[test]
+    fn test_checked_in_data_is_up_to_date() {
+        let spec = Spec::parse(&workspace_file(SPEC_PATH)).unwrap();
+        assert_eq!(
+            generate(&spec, SPEC_PATH),
+            workspace_file("src/msg/params/data.rs"),
+            "run `cargo run -p irs-codegen`"
+        );
+    }
+
+    #[test]
+    fn test_field_types_and_validation() {
+        let spec = Spec::parse(
+            r#"
+            [[param]]
+            id = 9
+            name = "Req"
+            [[param]]
+            id = 10
+            name = "Resp"
+            struct = "Status"
+            response_to = 9
+            field = [
+                { name = "offset", width = 16, signed = true, unit = "mm" },
+                { name = "count", width = 32 },
+            ]
+            "#,
+        )
+        .unwrap();
+        let src = generate(&spec, "test.toml");
+        assert!(src.contains("pub struct Param9;"));
+        assert!(src.contains("#[irs(id = 10, response_to = 9, name = \"Resp\")]"));
+        assert!(src.contains("    /// Unit: mm.\r\n    pub offset: i16,\r\n    pub count: u32,"));
+
+        for bad in [
+            "[[param]]\nid = 1\nname = \"A\"\n[[param]]\nid = 1\nname = \"B\"",
+            "[[param]]\nid = 1\nname = \"A\"\nresponse_to = 2",
+            "[[param]]\nid = 1\nname = \"A\"\nfield = [{ name = \"x\", width = 12 }]",
+            "[[param]]\nid = 1\nname = \"not ok\"",
+        ] {
+            assert!(
+                matches!(Spec::parse(bad), Err(CodegenError::Invalid(_))),
+                "{bad}"
+            );
+        }
+        assert!(matches!(
+            Spec::parse("[[param]]\nid = 1"),
+            Err(CodegenError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,73 @@
+//! Regenerates `src/msg/params/data.rs` from `spec/params.toml`.
+//!
+//! ```text
+//! cargo run -p irs-codegen [-- [--check] [SPEC [OUT]]]
+//! ```
+//!
+//! Paths default to the ones in this workspace. With `--check` nothing is
+//! written and the exit status tells whether `OUT` is up to date.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use irs_codegen::{Spec, generate};
+
+const SPEC: &str = "spec/params.toml";
+const OUT: &str = "src/msg/params/data.rs";
+
+fn main() -> ExitCode {
+    let mut check = false;
+    let mut paths = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--check" => check = true,
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .expect("irs-codegen lives in the workspace root");
+    let mut paths = paths.into_iter();
+    let spec_path = paths.next().unwrap_or_else(|| root.join(SPEC));
+    let out_path = paths.next().unwrap_or_else(|| root.join(OUT));
+
+    let src = match fs::read_to_string(&spec_path) {
+        Ok(src) => src,
+        Err(e) => {
+            eprintln!("{}: {e}", spec_path.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    let spec = match Spec::parse(&src) {
+        Ok(spec) => spec,
+        Err(e) => {
+            eprintln!("{}: {e}", spec_path.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    // name the spec the way the generated header does for the default paths
+    let shown = spec_path
+        .strip_prefix(root)
+        .unwrap_or(&spec_path)
+        .display()
+        .to_string();
+    let code = generate(&spec, &shown);
+
+    if check {
+        let current = fs::read_to_string(&out_path).unwrap_or_default();
+        if current != code {
+            eprintln!("{} is out of date", out_path.display());
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
+    if let Err(e) = fs::write(&out_path, code) {
+        eprintln!("{}: {e}", out_path.display());
+        return ExitCode::FAILURE;
+    }
+    println!("wrote {}", out_path.display());
+    ExitCode::SUCCESS
+}
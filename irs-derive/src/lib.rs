@@ -0,0 +1,89 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{DeriveInput, LitInt, LitStr, parse_macro_input};
+
+/// Implements `irs_rs::msg::params::IrsParam` for a parameter struct and
+/// registers it, so frames carrying its ID decode into it.
+///
+/// ```ignore
+/// #[derive(Debug, Serialize, Deserialize, IrsParam)]
+/// #[irs(id = 471, response_to = 470, name = "GetCuttingHeightResp")]
+/// pub struct Param471 { /* ... */ }
+/// ```
+///
+/// `id` is required; `response_to` names the request parameter this one
+/// answers; `name` defaults to the struct name. Encoding goes through the
+/// crate's binary serializer, so the struct must implement serde's
+/// `Serialize` and `Deserialize`.
+#[proc_macro_derive(IrsParam, attributes(irs))]
+pub fn derive_irs_param(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "IrsParam cannot be derived for generic types",
+        ));
+    }
+    let ident = &input.ident;
+    let mut id = None;
+    let mut response_to = None;
+    let mut name = None;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("irs")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("id") {
+                id = Some(meta.value()?.parse::<LitInt>()?.base10_parse::<u16>()?);
+            } else if meta.path.is_ident("response_to") {
+                response_to = Some(meta.value()?.parse::<LitInt>()?.base10_parse::<u16>()?);
+            } else if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else {
+                return Err(meta.error("expected `id`, `response_to` or `name`"));
+            }
+            Ok(())
+        })?;
+    }
+    let Some(id) = id else {
+        return Err(syn::Error::new_spanned(
+            ident,
+            "missing #[irs(id = ...)] attribute",
+        ));
+    };
+    let name = name.unwrap_or_else(|| ident.to_string());
+    let response_to = match response_to {
+        Some(req) => quote!(::core::option::Option::Some(#req)),
+        None => quote!(::core::option::Option::None),
+    };
+
+    Ok(quote! {
+        impl ::irs_rs::msg::params::IrsParam for #ident {
+            const ID: u16 = #id;
+            const NAME: &'static str = #name;
+            const RESPONSE_TO: ::core::option::Option<u16> = #response_to;
+
+            fn encode(
+                &self,
+            ) -> ::core::result::Result<
+                ::std::vec::Vec<u8>,
+                ::irs_rs::msg::serialization::BinarySerializeError,
+            > {
+                ::irs_rs::msg::serialization::serialize(self)
+            }
+            fn decode(
+                bytes: &[u8],
+            ) -> ::core::result::Result<Self, ::irs_rs::msg::serialization::BinarySerializeError>
+            {
+                ::irs_rs::msg::serialization::deserialize(bytes)
+            }
+        }
+
+        ::irs_rs::__private::inventory::submit! {
+            ::irs_rs::msg::params::ParamInfo::of::<#ident>()
+        }
+    })
+}
@@ -0,0 +1,103 @@
+//! Line commands for changing a [`Simulator`]'s state from a script.
+//!
+//! ```text
+//! show                 print the state
+//! height MM            current cutting height
+//! default MM           default cutting height
+//! info N               information byte of the responses
+//! code N               return code byte of the responses
+//! ```
+//!
+//! Blank lines and lines starting with `#` are ignored.
+
+use std::fmt;
+
+use crate::{MowerState, Simulator};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CommandError {
+    Unknown(String),
+    /// The command's argument is missing or not understood.
+    BadValue(&'static str),
+}
+
+impl std::error::Error for CommandError {}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Unknown(cmd) => write!(f, "unknown command `{cmd}`"),
+            CommandError::BadValue(cmd) => write!(f, "bad value for `{cmd}`"),
+        }
+    }
+}
+
+/// Run one command line against `sim`, returning the state afterwards, or
+/// `None` for a blank or comment line.
+pub fn apply(sim: &Simulator, line: &str) -> Result<Option<MowerState>, CommandError> {
+    let mut words = line.split_whitespace();
+    let Some(cmd) = words.next().filter(|w| !w.starts_with('#')) else {
+        return Ok(None);
+    };
+    let arg = words.next();
+    let state = match cmd {
+        "show" => sim.state(),
+        "height" => {
+            let mm = number(arg).ok_or(CommandError::BadValue("height"))?;
+            sim.update(|s| s.current_cutting_height = mm)
+        }
+        "default" => {
+            let mm = number(arg).ok_or(CommandError::BadValue("default"))?;
+            sim.update(|s| s.default_cutting_height = mm)
+        }
+        "info" => {
+            let info = number(arg).ok_or(CommandError::BadValue("info"))?;
+            sim.update(|s| s.information = info)
+        }
+        "code" => {
+            let code = number(arg).ok_or(CommandError::BadValue("code"))?;
+            sim.update(|s| s.return_code = code)
+        }
+        other => return Err(CommandError::Unknown(other.to_string())),
+    };
+    Ok(Some(state))
+}
+
+fn number(arg: Option<&str>) -> Option<u8> {
+    arg?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let sim = Simulator::new();
+        assert_eq!(apply(&sim, "  # set up").unwrap(), None);
+        apply(&sim, "height 25").unwrap();
+        apply(&sim, "info 2").unwrap();
+        let state = apply(&sim, "code 9").unwrap().unwrap();
+        assert_eq!(
+            state,
+            MowerState {
+                default_cutting_height: 40,
+                current_cutting_height: 25,
+                information: 2,
+                return_code: 9,
+            }
+        );
+        assert_eq!(
+            apply(&sim, "height tall"),
+            Err(CommandError::BadValue("height"))
+        );
+        assert_eq!(
+            apply(&sim, "info blocked"),
+            Err(CommandError::BadValue("info"))
+        );
+        assert_eq!(
+            apply(&sim, "mow"),
+            Err(CommandError::Unknown("mow".to_string()))
+        );
+    }
+}
@@ -0,0 +1,273 @@
+//! A simulated mower main board, for developing against without hardware.
+//!
+//! A [`Simulator`] accepts Connect and ConnectExtended, then answers the
+//! cutting height request (470) from a shared [`MowerState`].
+//! Clones share that state, so a test can keep one to change what the board
+//! reports while another serves a TCP port or a pty. The `irs-sim` binary
+//! wraps this and reads [`command`]s from stdin.
+
+use std::{
+    fmt,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use irs_rs::{
+    error::IrsError,
+    msg::{
+        Msg,
+        header::DeviceCode,
+        params::{
+            IrsParam, Param,
+            data::{Param470, Param471},
+        },
+    },
+    session::{Session, key_exchange::KeyExchange},
+    transport::{
+        Transport,
+        tcp::{TcpServer, TcpTransport},
+    },
+};
+use tracing::{debug, info, warn};
+
+pub mod command;
+#[cfg(unix)]
+pub mod pty;
+
+/// What the simulated board reports about its cutting deck. The return code
+/// and information are raw bytes, as their values are not confirmed yet.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MowerState {
+    /// Unit: mm.
+    pub default_cutting_height: u8,
+    /// Unit: mm.
+    pub current_cutting_height: u8,
+    pub information: u8,
+    /// Answered to every request.
+    pub return_code: u8,
+}
+
+impl Default for MowerState {
+    fn default() -> Self {
+        MowerState {
+            default_cutting_height: 40,
+            current_cutting_height: 40,
+            information: 0,
+            return_code: 0,
+        }
+    }
+}
+
+impl MowerState {
+    pub fn param471(&self) -> Param471 {
+        Param471 {
+            return_code: self.return_code,
+            default_cutting_height: self.default_cutting_height,
+            current_cutting_height: self.current_cutting_height,
+            information: self.information,
+        }
+    }
+}
+
+impl fmt::Display for MowerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "height {} mm (default {} mm), info {}, code {}",
+            self.current_cutting_height,
+            self.default_cutting_height,
+            self.information,
+            self.return_code
+        )
+    }
+}
+
+/// The board side of IRS sessions, answering from a shared [`MowerState`].
+#[derive(Clone)]
+pub struct Simulator {
+    state: Arc<Mutex<MowerState>>,
+    sender: DeviceCode,
+    timeout: Duration,
+    key_exchange: Option<KeyExchange>,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulator {
+    pub fn new() -> Self {
+        Simulator {
+            state: Arc::default(),
+            sender: DeviceCode::MowerMainBoardApplicationSw,
+            timeout: Duration::from_secs(1),
+            key_exchange: None,
+        }
+    }
+    pub fn with_state(self, state: MowerState) -> Self {
+        self.set_state(state);
+        self
+    }
+    /// Device code to answer as, unless an extended connect asks for another.
+    pub fn with_sender(mut self, v: DeviceCode) -> Self {
+        self.sender = v;
+        self
+    }
+    /// How long to wait for each frame of the handshake.
+    pub fn with_timeout(mut self, v: Duration) -> Self {
+        self.timeout = v;
+        self
+    }
+    /// Require clients to agree on a session key when connecting.
+    pub fn with_key_exchange(mut self, v: KeyExchange) -> Self {
+        self.key_exchange = Some(v);
+        self
+    }
+
+    pub fn state(&self) -> MowerState {
+        *self.state.lock().unwrap()
+    }
+    pub fn set_state(&self, state: MowerState) {
+        *self.state.lock().unwrap() = state;
+    }
+    /// Change the state in place, e.g. from a test script.
+    pub fn update(&self, f: impl FnOnce(&mut MowerState)) -> MowerState {
+        let mut state = self.state.lock().unwrap();
+        f(&mut state);
+        *state
+    }
+
+    /// Answer the parameters of `request` this board knows. `None` if
+    /// there are none, in which case nothing should be sent back.
+    pub fn handle(&self, request: &Msg) -> Option<Msg> {
+        let mut reply = Msg::new();
+        reply.set_msg_id(request.get_msg_id());
+        let state = self.state.lock().unwrap();
+        for param in request.payload().get_params() {
+            let response = match param.id {
+                Param470::ID => Param::of(state.param471()),
+                other => {
+                    debug!("ignoring parameter {other}");
+                    continue;
+                }
+            };
+            reply.add_param(response);
+        }
+        (!reply.payload().get_params().is_empty()).then_some(reply)
+    }
+
+    /// A board session over `transport`, configured from this simulator.
+    pub fn session<T: Transport>(&self, transport: T) -> Session<T> {
+        let session = Session::new(transport)
+            .with_sender(self.sender)
+            .with_timeout(self.timeout);
+        match &self.key_exchange {
+            Some(kx) => session.with_key_exchange(kx.clone()),
+            None => session,
+        }
+    }
+
+    /// Wait for a client to connect on `session`, then answer its requests
+    /// until it disconnects.
+    pub fn serve_session<T: Transport>(&self, session: &mut Session<T>) -> Result<(), IrsError> {
+        loop {
+            match session.accept() {
+                Err(IrsError::Timeout) => continue,
+                other => break other?,
+            }
+        }
+        info!("client {} connected", session.client_id());
+        loop {
+            let request = match session.recv() {
+                Ok(msg) => msg,
+                Err(IrsError::Timeout) => continue,
+                Err(IrsError::Closed) => {
+                    info!("client {} disconnected", session.client_id());
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            };
+            if let Some(reply) = self.handle(&request) {
+                session.send(reply)?;
+            }
+        }
+    }
+
+    /// Serve one connection at a time on `transport`, forever. Failed
+    /// sessions are logged and the board goes back to waiting for a
+    /// connect; only I/O errors end the loop.
+    pub fn serve_link<T: Transport>(&self, mut transport: T) -> Result<(), IrsError> {
+        loop {
+            let mut session = self.session(transport);
+            match self.serve_session(&mut session) {
+                Ok(()) => {}
+                Err(IrsError::Io(e)) => return Err(IrsError::Io(e)),
+                Err(e) => warn!("session ended: {e}"),
+            }
+            transport = session.into_inner();
+        }
+    }
+
+    /// Serve clients opening the slave side of `pty`, one at a time.
+    #[cfg(unix)]
+    pub fn serve_pty(&self, pty: pty::Pty) -> Result<(), IrsError> {
+        let pty::Pty {
+            master,
+            slave: _slave,
+            ..
+        } = pty;
+        self.serve_link(master)
+    }
+
+    /// Serve every client of `server` on its own thread, forever.
+    pub fn serve_tcp(&self, server: &TcpServer) -> Result<(), IrsError> {
+        let sim = self.clone();
+        server.serve(move |transport: TcpTransport, addr: SocketAddr| {
+            let mut session = sim.session(transport);
+            if let Err(e) = sim.serve_session(&mut session) {
+                warn!("{addr}: {e}");
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use irs_rs::{client::Client, transport::memory};
+
+    use super::*;
+
+    #[test]
+    fn test_answers_from_shared_state() {
+        let sim = Simulator::new().with_state(MowerState {
+            default_cutting_height: 35,
+            ..MowerState::default()
+        });
+        let (a, b) = memory::pair();
+        let board = sim.clone();
+        let server = thread::spawn(move || board.serve_session(&mut board.session(b)));
+
+        let mut session = Session::new(a).with_timeout(Duration::from_millis(500));
+        session.connect().unwrap();
+        let mut client = Client::new(session).with_timeout(Duration::from_millis(500));
+        let state = client.cutting_height().unwrap();
+        assert_eq!(state.default_cutting_height, 35);
+        assert_eq!(state.current_cutting_height, 40);
+
+        sim.update(|s| {
+            s.information = 1;
+            s.return_code = 3;
+        });
+        let state = client.cutting_height().unwrap();
+        assert_eq!(state.information, 1);
+        assert_eq!(state.return_code, 3);
+
+        client.session_mut().disconnect().unwrap();
+        server.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+//! Runs a simulated mower main board.
+//!
+//! ```text
+//! cargo run -p irs-sim -- [--tcp ADDR | --pty] [--height MM] [--default MM]
+//!                         [--psk KEY] [--x25519]
+//! ```
+//!
+//! Listens on TCP `127.0.0.1:4700` unless given another address or `--pty`,
+//! in which case it prints the device path for clients to open. `--psk`
+//! and `--x25519` make clients agree on a session key when connecting.
+//! Lines on stdin are [`irs_sim::command`]s; each prints the new state.
+
+use std::{
+    io::{self, BufRead},
+    process::ExitCode,
+    thread,
+};
+
+use irs_rs::{session::key_exchange::KeyExchange, transport::tcp::TcpServer};
+use irs_sim::{MowerState, Simulator, command};
+
+const USAGE: &str =
+    "usage: irs-sim [--tcp ADDR | --pty] [--height MM] [--default MM] [--psk KEY] [--x25519]";
+const ADDR: &str = "127.0.0.1:4700";
+
+enum Listen {
+    Tcp(String),
+    Pty,
+}
+
+fn main() -> ExitCode {
+    tracing_subscriber::fmt().with_writer(io::stderr).init();
+    let (sim, listen) = match parse_args(std::env::args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let board = sim.clone();
+    let server = match listen {
+        Listen::Tcp(addr) => {
+            let server = match TcpServer::bind(&addr) {
+                Ok(server) => server,
+                Err(e) => {
+                    eprintln!("{addr}: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            match server.local_addr() {
+                Ok(local) => println!("listening on {local}"),
+                Err(_) => println!("listening on {addr}"),
+            }
+            thread::spawn(move || board.serve_tcp(&server))
+        }
+        #[cfg(unix)]
+        Listen::Pty => {
+            let pty = match irs_sim::pty::Pty::open() {
+                Ok(pty) => pty,
+                Err(e) => {
+                    eprintln!("pty: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            println!("listening on {}", pty.path());
+            thread::spawn(move || board.serve_pty(pty))
+        }
+        #[cfg(not(unix))]
+        Listen::Pty => {
+            eprintln!("--pty is only supported on unix");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!("{}", sim.state());
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else { break };
+        match command::apply(&sim, &line) {
+            Ok(Some(state)) => println!("{state}"),
+            Ok(None) => {}
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+    // stdin closed: keep serving until the listener fails
+    match server.join() {
+        Ok(Err(e)) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+        _ => ExitCode::SUCCESS,
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<(Simulator, Listen), String> {
+    let mut listen = Listen::Tcp(ADDR.to_string());
+    let mut state = MowerState::default();
+    let mut psk = None;
+    let mut x25519 = false;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--tcp" => listen = Listen::Tcp(value()?),
+            "--pty" => listen = Listen::Pty,
+            "--height" => state.current_cutting_height = millimetres(&value()?)?,
+            "--default" => state.default_cutting_height = millimetres(&value()?)?,
+            "--psk" => psk = Some(value()?.into_bytes()),
+            "--x25519" => x25519 = true,
+            _ => return Err(format!("unexpected argument `{arg}`")),
+        }
+    }
+    let sim = Simulator::new().with_state(state);
+    let key_exchange = match (x25519, psk) {
+        (true, Some(psk)) => KeyExchange::x25519().with_psk(psk),
+        (true, None) => KeyExchange::x25519(),
+        (false, Some(psk)) => KeyExchange::pre_shared(psk),
+        (false, None) => return Ok((sim, listen)),
+    };
+    Ok((sim.with_key_exchange(key_exchange), listen))
+}
+
+fn millimetres(value: &str) -> Result<u8, String> {
+    value
+        .parse()
+        .map_err(|_| format!("`{value}` is not a height in mm"))
+}
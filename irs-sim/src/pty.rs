@@ -0,0 +1,82 @@
+use std::{
+    fs::{File, OpenOptions},
+    os::unix::fs::OpenOptionsExt,
+};
+
+use serialport::{SerialPort, TTYPort};
+
+use irs_rs::{error::IrsError, transport::serial::SerialTransport};
+
+/// A pseudo-terminal the simulator listens on like a board on a UART.
+///
+/// The simulator reads and writes the master side; clients open
+/// [`Pty::path`] as they would the real serial port.
+pub struct Pty {
+    pub(crate) master: SerialTransport,
+    /// The client side, held open so the master does not see a hangup
+    /// between clients. A plain file rather than a port, as ports take a
+    /// lock that would keep clients from opening it exclusively.
+    pub(crate) slave: File,
+    path: String,
+}
+
+impl Pty {
+    pub fn open() -> Result<Self, IrsError> {
+        let (master, slave) = TTYPort::pair().map_err(std::io::Error::from)?;
+        let path = slave.name().unwrap_or_default();
+        let held = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&path)?;
+        drop(slave);
+        Ok(Pty {
+            master: SerialTransport::from_port(Box::new(master)),
+            slave: held,
+            path,
+        })
+    }
+
+    /// Device path of the client side, e.g. `/dev/pts/3`.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use irs_rs::{
+        client::Client,
+        session::{ConnectMode, Session},
+        transport::serial::SerialConfig,
+    };
+
+    use super::*;
+    use crate::Simulator;
+
+    #[test]
+    fn test_serve_pty() {
+        let pty = Pty::open().unwrap();
+        let path = pty.path().to_string();
+        let sim = Simulator::new();
+        let board = sim.clone();
+        thread::spawn(move || board.serve_pty(pty));
+
+        // legacy connects, and a second client after the first one leaves
+        for height in [30, 50] {
+            let port = SerialTransport::open(&SerialConfig::new(&path)).unwrap();
+            let mut session = Session::new(port)
+                .with_mode(ConnectMode::Legacy)
+                .with_timeout(Duration::from_secs(2));
+            session.connect().unwrap();
+            let mut client = Client::new(session);
+            sim.update(|s| s.current_cutting_height = height);
+            assert_eq!(
+                client.cutting_height().unwrap().current_cutting_height,
+                height
+            );
+            client.session_mut().disconnect().unwrap();
+        }
+    }
+}
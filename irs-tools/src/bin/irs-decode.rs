@@ -0,0 +1,113 @@
+//! Describes IRS frames field by field.
+//!
+//! ```text
+//! cargo run -p irs-tools --bin irs-decode -- [--json] [--spec SPEC] [--file PATH | HEX...]
+//! ```
+//!
+//! Reads the hex given as arguments, or else the file given with `--file`
+//! (`-` for stdin), or else stdin. Files and stdin may hold a hex dump or a
+//! binary capture; one holding several frames, or noise between them, is
+//! split into frames. Each field is printed with its offset, and each CRC
+//! with whether it matches. `--json` prints the same as a JSON array. The
+//! exit status is non-zero if anything failed to decode or a CRC is bad.
+
+use std::{
+    fs,
+    io::{self, Read},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use irs_tools::{
+    ToolError,
+    decode::{self, Item},
+    hex,
+    layout::Layout,
+};
+
+const USAGE: &str = "usage: irs-decode [--json] [--spec SPEC] [--file PATH | HEX...]";
+
+struct Args {
+    json: bool,
+    spec: Option<PathBuf>,
+    file: Option<PathBuf>,
+    hex: Vec<String>,
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(&args) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("irs-decode: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Decode and print; `Ok(false)` if some of the input was not a good frame.
+fn run(args: &Args) -> Result<bool, ToolError> {
+    let layout = match &args.spec {
+        Some(path) => Layout::from_file(path)?,
+        None => Layout::builtin(),
+    };
+    let capture = if !args.hex.is_empty() {
+        hex::parse(&args.hex.join(" "))?
+    } else {
+        let input = match args.file.as_deref() {
+            Some(path) if path.as_os_str() != "-" => fs::read(path)?,
+            _ => {
+                let mut input = Vec::new();
+                io::stdin().read_to_end(&mut input)?;
+                input
+            }
+        };
+        if hex::is_hex(&input) {
+            hex::parse(&String::from_utf8_lossy(&input))?
+        } else {
+            input
+        }
+    };
+
+    let items = decode::decode(&capture, &layout);
+    if args.json {
+        let json = serde_json::to_string_pretty(&items).map_err(io::Error::from)?;
+        println!("{json}");
+    } else {
+        for item in &items {
+            print!("{item}");
+        }
+    }
+    let good = |item: &Item| matches!(item, Item::Frame(frame) if frame.crc_ok());
+    Ok(!items.is_empty() && items.iter().all(good))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        json: false,
+        spec: None,
+        file: None,
+        hex: Vec::new(),
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--json" => parsed.json = true,
+            "--spec" => parsed.spec = Some(value()?.into()),
+            "--file" => parsed.file = Some(value()?.into()),
+            _ if arg.starts_with("--") => return Err(format!("unexpected argument `{arg}`")),
+            _ => parsed.hex.push(arg),
+        }
+    }
+    if parsed.file.is_some() && !parsed.hex.is_empty() {
+        return Err("give either --file or hex, not both".to_string());
+    }
+    Ok(parsed)
+}
@@ -0,0 +1,116 @@
+//! Encodes a frame from a TOML or JSON message description.
+//!
+//! ```text
+//! cargo run -p irs-tools --bin irs-encode -- [--bin] [--out PATH] [--spec SPEC]
+//!     [--bad-header-crc] [--bad-payload-crc] [--payload-length N] [--ue-len N]
+//!     [--truncate N] [DESCRIPTION]
+//! ```
+//!
+//! Reads the description from the file given, or else from stdin; see
+//! [`irs_tools::encode`] for its format. Prints the frame in hex, or writes
+//! it as is with `--bin`, to stdout or the `--out` file. The corruption
+//! options override those in the description's `[corrupt]` table.
+
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use irs_tools::{ToolError, encode::Description, hex, layout::Layout};
+
+const USAGE: &str = "usage: irs-encode [--bin] [--out PATH] [--spec SPEC] \
+    [--bad-header-crc] [--bad-payload-crc] [--payload-length N] [--ue-len N] \
+    [--truncate N] [DESCRIPTION]";
+
+#[derive(Default)]
+struct Args {
+    binary: bool,
+    out: Option<PathBuf>,
+    spec: Option<PathBuf>,
+    description: Option<PathBuf>,
+    header_crc: bool,
+    payload_crc: bool,
+    payload_length: Option<u16>,
+    ue_len: Option<u16>,
+    truncate: Option<usize>,
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("irs-encode: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &Args) -> Result<(), ToolError> {
+    let layout = match &args.spec {
+        Some(path) => Layout::from_file(path)?,
+        None => Layout::builtin(),
+    };
+    let text = match &args.description {
+        Some(path) => fs::read_to_string(path)?,
+        None => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            text
+        }
+    };
+    let mut description = Description::parse(&text)?;
+    let corrupt = &mut description.corrupt;
+    corrupt.header_crc |= args.header_crc;
+    corrupt.payload_crc |= args.payload_crc;
+    corrupt.payload_length = args.payload_length.or(corrupt.payload_length);
+    corrupt.ue_len = args.ue_len.or(corrupt.ue_len);
+    corrupt.truncate = args.truncate.unwrap_or(corrupt.truncate);
+    let frame = description.encode(&layout)?;
+
+    let output = if args.binary {
+        frame
+    } else {
+        format!("{}\n", hex::format(&frame)).into_bytes()
+    };
+    match &args.out {
+        Some(path) => fs::write(path, output)?,
+        None => io::stdout().write_all(&output)?,
+    }
+    Ok(())
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--bin" => parsed.binary = true,
+            "--out" => parsed.out = Some(value()?.into()),
+            "--spec" => parsed.spec = Some(value()?.into()),
+            "--bad-header-crc" => parsed.header_crc = true,
+            "--bad-payload-crc" => parsed.payload_crc = true,
+            "--payload-length" => parsed.payload_length = Some(number(&arg, &value()?)?),
+            "--ue-len" => parsed.ue_len = Some(number(&arg, &value()?)?),
+            "--truncate" => parsed.truncate = Some(number(&arg, &value()?)?),
+            _ if arg.starts_with("--") => return Err(format!("unexpected argument `{arg}`")),
+            _ if parsed.description.is_none() => parsed.description = Some(arg.into()),
+            _ => return Err(format!("unexpected argument `{arg}`")),
+        }
+    }
+    Ok(parsed)
+}
+
+fn number<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{arg}: `{value}` is not a number"))
+}
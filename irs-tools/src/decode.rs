@@ -0,0 +1,523 @@
+//! Field-by-field description of captured frames.
+//!
+//! Every field is reported with its offset from the start of the capture,
+//! so a description can be laid next to the hex dump it came from.
+
+use std::fmt;
+
+use irs_rs::msg::{
+    CrcCheck, Msg,
+    header::{DeviceCode, Header, MsgType, VarField, VarHeader},
+    params::ParamRegistry,
+    payload::Payload,
+};
+use serde::Serialize;
+
+use crate::{hex, layout::Layout};
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+
+/// One field of a frame.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Field {
+    pub name: String,
+    pub offset: usize,
+    pub len: usize,
+    pub value: i128,
+    /// What the value stands for, e.g. the message type or unit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meaning: Option<String>,
+}
+
+/// A CRC field and whether it matches the bytes it covers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CrcField {
+    pub offset: usize,
+    pub value: u16,
+    pub expected: u16,
+    pub ok: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ParamView {
+    /// Offset of the parameter ID; the data starts four bytes later.
+    pub offset: usize,
+    pub id: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Length of the data.
+    pub len: usize,
+    /// The data by field, if the spec describes this parameter and the
+    /// length matches.
+    pub fields: Vec<Field>,
+    /// The data in hex, if it could not be split into fields.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+}
+
+/// Bytes shown as they are, such as the encrypted part of a payload.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub offset: usize,
+    pub len: usize,
+    pub hex: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Frame {
+    pub offset: usize,
+    pub len: usize,
+    pub header: Vec<Field>,
+    pub header_crc: CrcField,
+    pub var_header: Vec<Field>,
+    pub payload: Vec<Field>,
+    pub params: Vec<ParamView>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encrypted: Option<Span>,
+    pub payload_crc: CrcField,
+}
+
+impl Frame {
+    pub fn crc_ok(&self) -> bool {
+        self.header_crc.ok && self.payload_crc.ok
+    }
+}
+
+/// What [`decode`] found in a capture, in order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Item {
+    Frame(Frame),
+    /// Bytes that start like a frame but do not decode: the whole frame if
+    /// its header CRC matches, else up to the next SOH STX. Error offsets
+    /// are relative to `offset`.
+    Invalid {
+        offset: usize,
+        len: usize,
+        error: String,
+    },
+    /// Bytes between frames, e.g. line noise.
+    Skipped {
+        offset: usize,
+        len: usize,
+    },
+}
+
+/// Split `capture` into frames and describe each.
+///
+/// Frames are found by their SOH STX start and the length their header
+/// announces; a frame cut short by the end of the capture is reported as
+/// invalid. An invalid frame whose header CRC does not vouch for its length
+/// is cut at the next SOH STX, so a corrupt length does not swallow the
+/// frames behind it. CRC mismatches are reported in the frame rather than failing
+/// it, as the rest of a frame with a bad CRC is usually still of interest.
+pub fn decode(capture: &[u8], layout: &Layout) -> Vec<Item> {
+    let mut items = Vec::new();
+    let mut noise_from = None;
+    let mut at = 0;
+    while at < capture.len() {
+        let rest = &capture[at..];
+        if !rest.starts_with(&[SOH, STX]) {
+            noise_from.get_or_insert(at);
+            at += 1;
+            continue;
+        }
+        if let Some(from) = noise_from.take() {
+            items.push(Item::Skipped {
+                offset: from,
+                len: at - from,
+            });
+        }
+        // without a usable header, report just the header and look further
+        let len = frame_len(rest).unwrap_or(Header::SIZE).min(rest.len());
+        let frame = &rest[..len];
+        match Msg::from_bytes_with(frame, CrcCheck::Lenient) {
+            Ok(_) => {
+                items.push(Item::Frame(describe(frame, at, layout)));
+                at += len;
+            }
+            Err(e) => {
+                // unless the header CRC vouches for the announced length, it
+                // may be what is wrong: resync on the next SOH STX instead
+                let len = if header_crc_ok(rest) {
+                    len
+                } else {
+                    next_start(&rest[1..]).map_or(rest.len(), |n| n + 1)
+                };
+                items.push(Item::Invalid {
+                    offset: at,
+                    len,
+                    error: e.to_string(),
+                });
+                at += len;
+            }
+        }
+    }
+    if let Some(from) = noise_from {
+        items.push(Item::Skipped {
+            offset: from,
+            len: capture.len() - from,
+        });
+    }
+    items
+}
+
+/// Offset of the first SOH STX in `bytes`.
+fn next_start(bytes: &[u8]) -> Option<usize> {
+    bytes.windows(2).position(|w| w == [SOH, STX])
+}
+
+/// Whether the header CRC of the frame at the start of `bytes` matches, as
+/// `FrameDecoder` requires before trusting `payload_length`.
+fn header_crc_ok(bytes: &[u8]) -> bool {
+    let Ok(header) = Header::try_from(bytes) else {
+        return false;
+    };
+    let Some(var_len) = VarHeader::default_size(header.msg_type) else {
+        return false;
+    };
+    let payload_at = Header::SIZE + usize::from(var_len);
+    bytes.len() >= payload_at
+        && Header::crc_over(
+            &bytes[..Header::CRC_OFFSET],
+            &bytes[Header::SIZE..payload_at],
+        ) == header.crc
+}
+
+/// Length of the frame at the start of `bytes` by its header, if the header
+/// is complete and names a known message type.
+fn frame_len(bytes: &[u8]) -> Option<usize> {
+    let header = Header::try_from(bytes).ok()?;
+    let var_len = VarHeader::default_size(header.msg_type)?;
+    Some(Header::SIZE + usize::from(var_len) + usize::from(header.payload_length))
+}
+
+/// Describe `frame`, which [`Msg::from_bytes_with`] has accepted, found at
+/// `base` in the capture.
+fn describe(frame: &[u8], base: usize, layout: &Layout) -> Frame {
+    let mut fields = Fields { frame, base, at: 0 };
+    let msg_type = MsgType::try_from(frame[2]).unwrap_or(MsgType::Undefined);
+
+    let header = vec![
+        fields.int("soh", 1, None),
+        fields.int("stx", 1, None),
+        fields.int("msg_type", 1, Some(format!("{msg_type:?}"))),
+        fields.int("payload_length", 2, None),
+    ];
+    let header_crc_at = fields.at;
+    fields.at += 2;
+
+    let var_start = fields.at;
+    let var_header = VarHeader::layout(msg_type)
+        .iter()
+        .map(|&field| {
+            let value = fields.peek(field.size());
+            let meaning = match field {
+                VarField::Sender | VarField::Receiver => {
+                    Some(DeviceCode::from(value as u8).to_string())
+                }
+                _ => None,
+            };
+            fields.int(field.name(), field.size(), meaning)
+        })
+        .collect();
+    let payload_at = fields.at;
+    let header_crc = CrcField::check(
+        frame,
+        header_crc_at,
+        base,
+        Header::crc_over(&frame[..Header::CRC_OFFSET], &frame[var_start..payload_at]),
+    );
+
+    let payload = vec![fields.int("msg_id", 1, None), fields.int("ue_len", 2, None)];
+    let clear_end = fields.at + payload[1].value as usize;
+    let crc_at = frame.len() - 2;
+    let mut params = Vec::new();
+    while fields.at < clear_end {
+        params.push(fields.param(layout));
+    }
+    let encrypted = (clear_end < crc_at).then(|| Span {
+        offset: base + clear_end,
+        len: crc_at - clear_end,
+        hex: hex::format(&frame[clear_end..crc_at]),
+    });
+    let payload_crc = CrcField::check(frame, crc_at, base, Payload::crc_over(&frame[payload_at..]));
+
+    Frame {
+        offset: base,
+        len: frame.len(),
+        header,
+        header_crc,
+        var_header,
+        payload,
+        params,
+        encrypted,
+        payload_crc,
+    }
+}
+
+/// Reads fields off a frame front to back.
+struct Fields<'a> {
+    frame: &'a [u8],
+    base: usize,
+    at: usize,
+}
+
+impl Fields<'_> {
+    /// Little-endian value of the next `len` bytes, without consuming them.
+    fn peek(&self, len: usize) -> i128 {
+        self.peek_at(0, len)
+    }
+    /// Like [`Fields::peek`], `skip` bytes further on.
+    fn peek_at(&self, skip: usize, len: usize) -> i128 {
+        let mut le = [0; 8];
+        let from = self.at + skip;
+        le[..len].copy_from_slice(&self.frame[from..from + len]);
+        i128::from(u64::from_le_bytes(le))
+    }
+
+    fn int(&mut self, name: &str, len: usize, meaning: Option<String>) -> Field {
+        let field = Field {
+            name: name.to_string(),
+            offset: self.base + self.at,
+            len,
+            value: self.peek(len),
+            meaning,
+        };
+        self.at += len;
+        field
+    }
+
+    fn param(&mut self, layout: &Layout) -> ParamView {
+        let offset = self.base + self.at;
+        let id = self.peek(2) as u16;
+        let len = self.peek_at(2, 2) as usize;
+        self.at += 4;
+        let data = &self.frame[self.at..self.at + len];
+        let spec = layout.param(id);
+        let name = spec
+            .map(|p| p.name.clone())
+            .or_else(|| ParamRegistry::global().name(id).map(str::to_string));
+        let mut view = ParamView {
+            offset,
+            id,
+            name,
+            len,
+            fields: Vec::new(),
+            raw: None,
+        };
+        match spec.filter(|p| Layout::data_len(p) == len) {
+            Some(spec) => {
+                let mut at = 0;
+                for field in &spec.fields {
+                    let field_len = Layout::field_len(field);
+                    let value = Layout::read(field, &data[at..]);
+                    view.fields.push(Field {
+                        name: field.name.clone(),
+                        offset: self.base + self.at + at,
+                        len: field_len,
+                        value,
+                        meaning: field.unit.clone(),
+                    });
+                    at += field_len;
+                }
+            }
+            None if len > 0 => view.raw = Some(hex::format(data)),
+            None => {}
+        }
+        self.at += len;
+        view
+    }
+}
+
+impl CrcField {
+    fn check(frame: &[u8], at: usize, base: usize, expected: u16) -> Self {
+        let value = u16::from_le_bytes([frame[at], frame[at + 1]]);
+        CrcField {
+            offset: base + at,
+            value,
+            expected,
+            ok: value == expected,
+        }
+    }
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Item::Frame(frame) => frame.fmt(f),
+            Item::Invalid { offset, len, error } => {
+                writeln!(f, "invalid frame at {offset} ({len} bytes): {error}")
+            }
+            Item::Skipped { offset, len } => writeln!(f, "skipped {len} bytes at {offset}"),
+        }
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "frame at {} ({} bytes)", self.offset, self.len)?;
+        writeln!(f, "  header")?;
+        for field in &self.header {
+            write_field(f, field, 4)?;
+        }
+        write_crc(f, &self.header_crc)?;
+        writeln!(f, "  var header")?;
+        for field in &self.var_header {
+            write_field(f, field, 4)?;
+        }
+        writeln!(f, "  payload")?;
+        for field in &self.payload {
+            write_field(f, field, 4)?;
+        }
+        for param in &self.params {
+            let name = param.name.as_deref().unwrap_or("unknown");
+            writeln!(
+                f,
+                "{:>6}      param {} {name}, {} bytes",
+                param.offset, param.id, param.len
+            )?;
+            for field in &param.fields {
+                write_field(f, field, 6)?;
+            }
+            if let Some(raw) = &param.raw {
+                writeln!(f, "{:>6}          {raw}", param.offset + 4)?;
+            }
+        }
+        if let Some(span) = &self.encrypted {
+            writeln!(
+                f,
+                "{:>6}      encrypted, {} bytes: {}",
+                span.offset, span.len, span.hex
+            )?;
+        }
+        write_crc(f, &self.payload_crc)
+    }
+}
+
+fn write_field(f: &mut fmt::Formatter<'_>, field: &Field, indent: usize) -> fmt::Result {
+    let name = format!("{:indent$}{}", "", field.name);
+    write!(f, "{:>6}  {name:<28} {}", field.offset, field.value)?;
+    match &field.meaning {
+        Some(meaning) => writeln!(f, " ({meaning})"),
+        None => writeln!(f),
+    }
+}
+
+fn write_crc(f: &mut fmt::Formatter<'_>, crc: &CrcField) -> fmt::Result {
+    write!(f, "{:>6}  {:<28} {:#06x}", crc.offset, "    crc", crc.value)?;
+    if crc.ok {
+        writeln!(f, " (ok)")
+    } else {
+        writeln!(f, " (bad, expected {:#06x})", crc.expected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    include!("../../testdata/frames.rs");
+
+    fn field(name: &str, offset: usize, value: i128, meaning: Option<&str>) -> Field {
+        Field {
+            name: name.to_string(),
+            offset,
+            len: 1,
+            value,
+            meaning: meaning.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_decode_fields_and_offsets() {
+        let layout = Layout::builtin();
+        let items = decode(&ENCODED_471, &layout);
+        let [Item::Frame(frame)] = &items[..] else {
+            panic!("{items:?}");
+        };
+        assert!(frame.crc_ok());
+        assert_eq!(frame.header[2].meaning.as_deref(), Some("Data"));
+        assert_eq!(
+            frame.var_header[1],
+            field("sender", 11, 0x4d, Some("mower main board"))
+        );
+        let param = &frame.params[0];
+        assert_eq!(
+            (param.offset, param.id, param.name.as_deref()),
+            (16, 471, Some("GetCuttingHeightResp"))
+        );
+        assert_eq!(
+            param.fields,
+            [
+                field("return_code", 20, 0, None),
+                field("default_cutting_height", 21, 30, Some("mm")),
+                field("current_cutting_height", 22, 45, Some("mm")),
+                field("information", 23, 0, None),
+            ]
+        );
+        assert_eq!(frame.payload_crc.offset, 24);
+
+        let text = items[0].to_string();
+        assert!(text.contains("param 471 GetCuttingHeightResp, 4 bytes"));
+        assert!(text.contains("0xcabf (ok)"));
+        let json = serde_json::to_value(&items).unwrap();
+        assert_eq!(json[0]["kind"], "frame");
+        assert_eq!(json[0]["params"][0]["fields"][2]["value"], 45);
+    }
+
+    #[test]
+    fn test_decode_capture_with_noise_and_bad_crc() {
+        let mut capture = vec![0xff, 0x00];
+        capture.extend_from_slice(&ENCODED_471);
+        let mut corrupt = ENCODED_471;
+        corrupt[22] = 50;
+        capture.extend_from_slice(&corrupt);
+        // truncated after the header
+        capture.extend_from_slice(&ENCODED_471[..9]);
+
+        let items = decode(&capture, &Layout::builtin());
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[0], Item::Skipped { offset: 0, len: 2 });
+        let Item::Frame(second) = &items[2] else {
+            panic!("{items:?}");
+        };
+        assert_eq!(second.offset, 28);
+        assert!(second.header_crc.ok);
+        assert!(!second.payload_crc.ok);
+        assert_eq!(second.params[0].fields[2].value, 50);
+        assert!(matches!(
+            &items[3],
+            Item::Invalid {
+                offset: 54,
+                len: 9,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_decode_resyncs_after_bad_length() {
+        let mut corrupt = ENCODED_471;
+        corrupt[3..5].copy_from_slice(&0xffffu16.to_le_bytes());
+        let mut capture = corrupt.to_vec();
+        capture.extend_from_slice(&ENCODED_471);
+
+        let items = decode(&capture, &Layout::builtin());
+        assert_eq!(items.len(), 2, "{items:?}");
+        assert!(matches!(
+            &items[0],
+            Item::Invalid {
+                offset: 0,
+                len: 26,
+                ..
+            }
+        ));
+        let Item::Frame(frame) = &items[1] else {
+            panic!("{items:?}");
+        };
+        assert_eq!(frame.offset, 26);
+        assert!(frame.crc_ok());
+    }
+}
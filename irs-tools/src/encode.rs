@@ -0,0 +1,375 @@
+//! Frames built from a message description, for crafting test input.
+//!
+//! A description is TOML or JSON. Every key is optional; the var header
+//! defaults to those of [`Msg::new`]. In TOML:
+//!
+//! ```toml
+//! type = "Data"                # MsgType name or number
+//! msg_id = 5
+//! client_id = 1
+//! sender = "MobileApp"         # DeviceCode name or number
+//! receiver = "MowerMainBoardApplicationSw"
+//! # protocol_id, protocol_version, keepalive (seconds) and
+//! # connect_return_code (a number) for the connect message types
+//!
+//! [[param]]
+//! id = 471                     # or `name = "GetCuttingHeightResp"`
+//! return_code = 0              # every field of the spec, by name
+//! default_cutting_height = 40
+//! current_cutting_height = 45
+//! information = 0
+//!
+//! [[param]]
+//! id = 0x7001
+//! raw = "01 02 03"             # data bytes as is, for any parameter
+//!
+//! [corrupt]                    # see Corruption
+//! payload_crc = true
+//! ```
+//!
+//! Field values are numbers, checked against the width of their field.
+
+use std::collections::BTreeMap;
+
+use irs_rs::msg::{
+    Msg,
+    header::{DeviceCode, Header, MsgType, VarHeader},
+    params::{Param, ParamPayload, ParamRegistry},
+    payload::Payload,
+};
+use serde::Deserialize;
+
+use crate::{ToolError, hex, layout::Layout};
+
+/// A number, or the name of what it stands for.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Number(i64),
+    Name(String),
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Description {
+    #[serde(rename = "type")]
+    pub msg_type: Option<Value>,
+    pub msg_id: Option<u8>,
+    pub client_id: Option<u32>,
+    pub sender: Option<Value>,
+    pub receiver: Option<Value>,
+    pub protocol_id: Option<u8>,
+    pub protocol_version: Option<u8>,
+    pub keepalive: Option<u16>,
+    pub connect_return_code: Option<Value>,
+    #[serde(rename = "param", default)]
+    pub params: Vec<ParamDescription>,
+    #[serde(default)]
+    pub corrupt: Corruption,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ParamDescription {
+    pub name: Option<String>,
+    /// Alongside a name, sends the named parameter's data under this ID.
+    pub id: Option<u16>,
+    /// The data in hex, instead of by field.
+    pub raw: Option<String>,
+    #[serde(flatten)]
+    pub fields: BTreeMap<String, Value>,
+}
+
+/// Deliberate damage to an encoded frame, for negative tests.
+///
+/// The length overrides keep the CRC over them valid, so that the length is
+/// the only thing wrong unless a CRC is spoiled as well.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Corruption {
+    /// Spoil the header CRC.
+    #[serde(default)]
+    pub header_crc: bool,
+    /// Spoil the payload CRC.
+    #[serde(default)]
+    pub payload_crc: bool,
+    /// Announce this payload length in the header instead of the real one.
+    pub payload_length: Option<u16>,
+    /// Announce this many clear parameter bytes instead of the real number.
+    pub ue_len: Option<u16>,
+    /// Cut this many bytes off the end.
+    #[serde(default)]
+    pub truncate: usize,
+}
+
+impl Description {
+    /// Parse a description, as JSON if it starts with `{` and TOML otherwise.
+    pub fn parse(text: &str) -> Result<Self, ToolError> {
+        let parsed = if text.trim_start().starts_with('{') {
+            serde_json::from_str(text).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(text).map_err(|e| e.to_string())
+        };
+        parsed.map_err(ToolError::BadDescription)
+    }
+
+    /// The frame described, with [`Description::corrupt`] applied.
+    pub fn encode(&self, layout: &Layout) -> Result<Vec<u8>, ToolError> {
+        let mut msg = Msg::new();
+        if let Some(v) = &self.msg_type {
+            msg.set_message_type(msg_type(v)?);
+        }
+        if let Some(v) = self.msg_id {
+            msg.set_msg_id(v);
+        }
+        let vh = msg.var_header_mut();
+        if let Some(v) = self.client_id {
+            vh.client_id = Some(v);
+        }
+        if let Some(v) = &self.sender {
+            vh.sender = Some(device_code("sender", v)?);
+        }
+        if let Some(v) = &self.receiver {
+            vh.receiver = Some(device_code("receiver", v)?);
+        }
+        if let Some(v) = self.protocol_id {
+            vh.protocol_id = Some(v);
+        }
+        if let Some(v) = self.protocol_version {
+            vh.protocol_version = Some(v);
+        }
+        if let Some(v) = self.keepalive {
+            vh.set_keepalive(v);
+        }
+        if let Some(v) = &self.connect_return_code {
+            vh.connect_return_code = Some(byte("connect_return_code", v)?);
+        }
+        for param in &self.params {
+            let (id, data) = param.encode(layout)?;
+            msg.add_param(Param::new(id, ParamPayload::raw(id, data)));
+        }
+        let mut bytes = msg.to_bytes()?;
+        self.corrupt.apply(&mut bytes);
+        Ok(bytes)
+    }
+}
+
+impl ParamDescription {
+    /// The parameter's ID and data bytes.
+    pub fn encode(&self, layout: &Layout) -> Result<(u16, Vec<u8>), ToolError> {
+        let bad = |msg: String| ToolError::BadDescription(msg);
+        let spec = match (&self.name, self.id) {
+            (Some(name), _) => layout.param_named(name),
+            (None, Some(id)) => layout.param(id),
+            (None, None) => return Err(bad("a param needs a name or an id".into())),
+        };
+        let id = match (self.id, spec) {
+            (Some(id), _) => id,
+            (None, Some(spec)) => spec.id,
+            // not in the spec, but maybe registered, e.g. the key exchange
+            (None, None) => {
+                let name = self.name.as_deref().unwrap_or_default();
+                ParamRegistry::global()
+                    .iter()
+                    .find(|info| info.name == name)
+                    .map(|info| info.id)
+                    .ok_or_else(|| bad(format!("unknown parameter {name}")))?
+            }
+        };
+        if let Some(raw) = &self.raw {
+            if !self.fields.is_empty() {
+                return Err(bad(format!("parameter {id}: give either raw or fields")));
+            }
+            return Ok((id, hex::parse(raw)?));
+        }
+        let Some(spec) = spec else {
+            if self.fields.is_empty() {
+                return Ok((id, Vec::new()));
+            }
+            return Err(bad(format!(
+                "parameter {id} is not in the spec, give its data as raw"
+            )));
+        };
+        if let Some(extra) = self
+            .fields
+            .keys()
+            .find(|name| !spec.fields.iter().any(|f| &&f.name == name))
+        {
+            return Err(bad(format!("{} has no field {extra}", spec.name)));
+        }
+        let mut data = Vec::new();
+        for field in &spec.fields {
+            let value = match self.fields.get(&field.name) {
+                Some(Value::Number(n)) => i128::from(*n),
+                Some(Value::Name(name)) => {
+                    return Err(bad(format!("{} is {name:?}, not a number", field.name)));
+                }
+                None => return Err(bad(format!("{} needs {}", spec.name, field.name))),
+            };
+            Layout::write(field, value, &mut data).map_err(bad)?;
+        }
+        Ok((id, data))
+    }
+}
+
+impl Corruption {
+    /// Damage `frame`, a well-formed encoded frame.
+    pub fn apply(&self, frame: &mut Vec<u8>) {
+        let msg_type = MsgType::try_from(frame[2]).unwrap_or(MsgType::Undefined);
+        let var_len = usize::from(VarHeader::default_size(msg_type).unwrap_or_default());
+        let payload_at = Header::SIZE + var_len;
+        if let Some(ue_len) = self.ue_len {
+            frame[payload_at + 1..payload_at + 3].copy_from_slice(&ue_len.to_le_bytes());
+            let crc_at = frame.len() - 2;
+            let crc = Payload::crc_over(&frame[payload_at..]);
+            frame[crc_at..].copy_from_slice(&crc.to_le_bytes());
+        }
+        if let Some(len) = self.payload_length {
+            frame[3..5].copy_from_slice(&len.to_le_bytes());
+            let crc = Header::crc_over(
+                &frame[..Header::CRC_OFFSET],
+                &frame[Header::SIZE..payload_at],
+            );
+            frame[Header::CRC_OFFSET..Header::SIZE].copy_from_slice(&crc.to_le_bytes());
+        }
+        if self.header_crc {
+            frame[Header::CRC_OFFSET] ^= 0xff;
+        }
+        if self.payload_crc {
+            let crc_at = frame.len() - 2;
+            frame[crc_at] ^= 0xff;
+        }
+        frame.truncate(frame.len().saturating_sub(self.truncate));
+    }
+}
+
+/// The value among `all` whose `Debug` name is `name`.
+fn by_name<T: std::fmt::Debug>(all: impl Iterator<Item = T>, name: &str) -> Option<T> {
+    all.into_iter().find(|v| format!("{v:?}") == name)
+}
+
+fn byte(what: &str, value: &Value) -> Result<u8, ToolError> {
+    match value {
+        Value::Number(n) => u8::try_from(*n)
+            .map_err(|_| ToolError::BadDescription(format!("{what} is {n}, not a byte"))),
+        Value::Name(name) => Err(ToolError::BadDescription(format!(
+            "{what}: unknown name {name}"
+        ))),
+    }
+}
+
+fn msg_type(value: &Value) -> Result<MsgType, ToolError> {
+    let all = || (0..=u8::MAX).filter_map(|b| MsgType::try_from(b).ok());
+    let found = match value {
+        Value::Name(name) => by_name(all(), name),
+        Value::Number(_) => MsgType::try_from(byte("type", value)?).ok(),
+    };
+    found.ok_or_else(|| ToolError::BadDescription(format!("unknown message type {value:?}")))
+}
+
+fn device_code(what: &str, value: &Value) -> Result<DeviceCode, ToolError> {
+    match value {
+        Value::Name(name) => by_name((0..=u8::MAX).map(DeviceCode::from), name)
+            .ok_or_else(|| ToolError::BadDescription(format!("{what}: unknown device {name}"))),
+        Value::Number(_) => Ok(DeviceCode::from(byte(what, value)?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use irs_rs::msg::CrcCheck;
+
+    use super::*;
+    use crate::decode::{self, Item};
+
+    include!("../../testdata/frames.rs");
+
+    #[test]
+    fn test_encode_toml_and_json() {
+        let layout = Layout::builtin();
+        let toml = r#"
+            type = "Data"
+            msg_id = 5
+            client_id = 1
+            sender = "PcConnectedToMainBoardUartInterface"
+            receiver = 0x4d
+
+            [[param]]
+            name = "GetCuttingHeightReq"
+        "#;
+        let bytes = Description::parse(toml).unwrap().encode(&layout).unwrap();
+        assert_eq!(bytes, ENCODED_470);
+
+        let json = r#"{
+            "msg_id": 6,
+            "param": [
+                {"name": "Param471", "return_code": 3,
+                 "default_cutting_height": 30, "current_cutting_height": 90,
+                 "information": 7},
+                {"id": 28928, "raw": "0a0b"}
+            ]
+        }"#;
+        let bytes = Description::parse(json).unwrap().encode(&layout).unwrap();
+        let msg = Msg::from_bytes(&bytes).unwrap();
+        let params = msg.payload().get_params();
+        assert_eq!(params[0].id, 471);
+        assert_eq!(params[0].to_bytes().unwrap()[4..], [3, 30, 90, 7]);
+        assert_eq!(
+            params[1].to_bytes().unwrap(),
+            [0x00, 0x71, 0x02, 0x00, 0x0a, 0x0b]
+        );
+    }
+
+    #[test]
+    fn test_description_errors() {
+        let layout = Layout::builtin();
+        let encode = |text: &str| Description::parse(text).and_then(|d| d.encode(&layout));
+        for text in [
+            "type = \"Bogus\"",
+            "sender = 300",
+            "[[param]]\nname = \"GetCuttingHeightResp\"",
+            "[[param]]\nid = 471\nreturn_code = 0\ndefault_cutting_height = 256\n\
+             current_cutting_height = 30\ninformation = 0",
+            "[[param]]\nname = \"GetCuttingHeightReq\"\nheight = 1",
+            "[[param]]\nname = \"NoSuchParam\"",
+            "colour = \"red\"",
+        ] {
+            assert!(
+                matches!(encode(text), Err(ToolError::BadDescription(_))),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_corruption() {
+        let layout = Layout::builtin();
+        let mut description =
+            Description::parse("msg_id = 1\n[[param]]\nname = \"GetCuttingHeightReq\"").unwrap();
+        let good = description.encode(&layout).unwrap();
+
+        description.corrupt.payload_crc = true;
+        let bytes = description.encode(&layout).unwrap();
+        let msg = Msg::from_bytes_with(&bytes, CrcCheck::Lenient).unwrap();
+        assert_eq!(msg.crc_errors().len(), 1);
+
+        description.corrupt = Corruption {
+            payload_length: Some(20),
+            ..Corruption::default()
+        };
+        let bytes = description.encode(&layout).unwrap();
+        assert!(matches!(
+            Msg::from_bytes(&bytes),
+            Err(irs_rs::error::IrsError::Truncated { .. })
+        ));
+
+        description.corrupt = Corruption {
+            ue_len: Some(2),
+            truncate: 1,
+            ..Corruption::default()
+        };
+        let bytes = description.encode(&layout).unwrap();
+        assert_eq!(bytes.len(), good.len() - 1);
+        let items = decode::decode(&bytes, &layout);
+        assert!(matches!(&items[..], [Item::Invalid { .. }]));
+    }
+}
@@ -0,0 +1,61 @@
+//! Hex dumps as they turn up in mower logs and bug reports.
+
+use crate::ToolError;
+
+/// Bytes of a hex dump such as `01 02 03`, `0x01,0x02` or `01:02:03`.
+///
+/// Whitespace, commas and colons separate bytes, a `0x` prefix is allowed
+/// on each group, and a group may hold several bytes (`010203`).
+pub fn parse(text: &str) -> Result<Vec<u8>, ToolError> {
+    let mut bytes = Vec::new();
+    let groups = text
+        .split(|c: char| c.is_ascii_whitespace() || c == ',' || c == ':')
+        .filter(|g| !g.is_empty());
+    for group in groups {
+        let digits = group
+            .strip_prefix("0x")
+            .or_else(|| group.strip_prefix("0X"))
+            .unwrap_or(group);
+        if digits.is_empty() || digits.len() % 2 != 0 || !digits.is_ascii() {
+            return Err(ToolError::BadHex(group.to_string()));
+        }
+        for pair in digits.as_bytes().chunks(2) {
+            // ASCII was checked above, so the pair is valid UTF-8
+            let pair = std::str::from_utf8(pair).unwrap_or_default();
+            let byte =
+                u8::from_str_radix(pair, 16).map_err(|_| ToolError::BadHex(group.to_string()))?;
+            bytes.push(byte);
+        }
+    }
+    Ok(bytes)
+}
+
+/// Whether `input` reads as a hex dump rather than a binary capture. Frames
+/// start with the control characters SOH and STX, so a capture never does.
+pub fn is_hex(input: &[u8]) -> bool {
+    input
+        .iter()
+        .all(|&b| b.is_ascii_hexdigit() || b.is_ascii_whitespace() || b",:xX".contains(&b))
+}
+
+/// `bytes` as space separated pairs, the way [`parse`] reads them back.
+pub fn format(bytes: &[u8]) -> String {
+    let pairs: Vec<String> = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    pairs.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse("01 02\n0x03,0X04:0506").unwrap(), [1, 2, 3, 4, 5, 6]);
+        assert_eq!(format(&[0x01, 0xab]), "01 ab");
+        assert!(matches!(parse("01 2"), Err(ToolError::BadHex(g)) if g == "2"));
+        assert!(matches!(parse("0x"), Err(ToolError::BadHex(_))));
+        assert!(matches!(parse("zz"), Err(ToolError::BadHex(_))));
+        assert!(is_hex(b"0x01, 02\n"));
+        assert!(!is_hex(&[0x01, 0x02, 0x03]));
+    }
+}
@@ -0,0 +1,84 @@
+use std::{fs, path::Path};
+
+use irs_codegen::{FieldSpec, ParamSpec, Spec};
+
+use crate::{SPEC, ToolError};
+
+/// Wire layout of the parameters described by a [`Spec`]: fields in order,
+/// integers little-endian.
+#[derive(Debug)]
+pub struct Layout {
+    spec: Spec,
+}
+
+impl Layout {
+    pub fn new(spec: Spec) -> Self {
+        Layout { spec }
+    }
+    /// The layout of the built-in spec.
+    pub fn builtin() -> Self {
+        Self::new(Spec::parse(SPEC).expect("built-in spec is valid"))
+    }
+    pub fn from_file(path: &Path) -> Result<Self, ToolError> {
+        Ok(Self::new(Spec::parse(&fs::read_to_string(path)?)?))
+    }
+    pub fn spec(&self) -> &Spec {
+        &self.spec
+    }
+
+    pub fn param(&self, id: u16) -> Option<&ParamSpec> {
+        self.spec.params.iter().find(|p| p.id == id)
+    }
+    /// The parameter called `name`, e.g. `GetCuttingHeightReq` or its
+    /// struct name `Param470`.
+    pub fn param_named(&self, name: &str) -> Option<&ParamSpec> {
+        self.spec
+            .params
+            .iter()
+            .find(|p| p.name == name || p.struct_name() == name)
+    }
+
+    /// Encoded size of `field` in bytes.
+    pub fn field_len(field: &FieldSpec) -> usize {
+        usize::from(field.width / 8)
+    }
+    /// Encoded size of the data of `param` in bytes.
+    pub fn data_len(param: &ParamSpec) -> usize {
+        param.fields.iter().map(Self::field_len).sum()
+    }
+
+    /// Value of `field` encoded at the start of `bytes`, which must hold
+    /// [`Layout::field_len`] bytes.
+    pub fn read(field: &FieldSpec, bytes: &[u8]) -> i128 {
+        let len = Self::field_len(field);
+        let mut le = [0; 8];
+        le[..len].copy_from_slice(&bytes[..len]);
+        let value = u64::from_le_bytes(le);
+        if field.signed && len < 8 {
+            // sign-extend from the field's width
+            let shift = 64 - 8 * len as u32;
+            i128::from(((value << shift) as i64) >> shift)
+        } else if field.signed {
+            i128::from(value as i64)
+        } else {
+            i128::from(value)
+        }
+    }
+
+    /// Append `value` as `field`, failing if it does not fit the field's
+    /// width.
+    pub fn write(field: &FieldSpec, value: i128, out: &mut Vec<u8>) -> Result<(), String> {
+        let len = Self::field_len(field);
+        let bits = 8 * len as u32;
+        let (min, max) = if field.signed {
+            (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1)
+        } else {
+            (0, (1i128 << bits) - 1)
+        };
+        if value < min || value > max {
+            return Err(format!("{} is {value}, outside {min}..={max}", field.name));
+        }
+        out.extend_from_slice(&(value as i64).to_le_bytes()[..len]);
+        Ok(())
+    }
+}
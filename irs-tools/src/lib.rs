@@ -0,0 +1,61 @@
+//! Shared pieces of the `irs-decode` and `irs-encode` command-line tools.
+//!
+//! Parameters are described by the spec the `params::data` module is
+//! generated from, `spec/params.toml`, which is built in; the tools take
+//! `--spec PATH` to describe a newer one without rebuilding.
+
+use std::fmt;
+
+use irs_codegen::CodegenError;
+use irs_rs::error::IrsError;
+
+pub mod decode;
+pub mod encode;
+pub mod hex;
+pub mod layout;
+
+/// The spec this build of `irs-rs` was generated from.
+pub const SPEC: &str = include_str!("../../spec/params.toml");
+
+#[derive(Debug)]
+pub enum ToolError {
+    Io(std::io::Error),
+    /// Input that should be hex is not.
+    BadHex(String),
+    Spec(CodegenError),
+    Irs(IrsError),
+    /// A message description that cannot be encoded.
+    BadDescription(String),
+}
+
+impl std::error::Error for ToolError {}
+
+impl fmt::Display for ToolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToolError::Io(e) => write!(f, "{e}"),
+            ToolError::BadHex(token) => write!(f, "bad hex `{token}`"),
+            ToolError::Spec(e) => write!(f, "{e}"),
+            ToolError::Irs(e) => write!(f, "{e}"),
+            ToolError::BadDescription(msg) => write!(f, "bad description: {msg}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for ToolError {
+    fn from(e: std::io::Error) -> Self {
+        ToolError::Io(e)
+    }
+}
+
+impl From<CodegenError> for ToolError {
+    fn from(e: CodegenError) -> Self {
+        ToolError::Spec(e)
+    }
+}
+
+impl From<IrsError> for ToolError {
+    fn from(e: IrsError) -> Self {
+        ToolError::Irs(e)
+    }
+}
@@ -0,0 +1,705 @@
+//! Request/response client for tokio applications.
+//!
+//! [`IrsClient`] is the asynchronous counterpart of
+//! [`Client`](crate::client::Client) over a [`Session`](crate::session::Session):
+//! it connects over any `AsyncRead + AsyncWrite` byte stream, then a
+//! background task decodes incoming frames and hands each response to the
+//! request waiting for it, so any number of requests can be in flight at
+//! once.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf},
+    sync::{mpsc, oneshot},
+    task::{AbortHandle, JoinHandle},
+    time,
+};
+use tracing::debug;
+
+#[cfg(feature = "key-exchange")]
+use crate::session::key_exchange::KeyExchange;
+use crate::{
+    client::pending::Requests,
+    error::IrsError,
+    msg::{
+        Msg,
+        cipher::SessionCipher,
+        frame::FrameDecoder,
+        header::{DeviceCode, MsgType, VarHeader},
+        params::{Param, ParamRegistry},
+    },
+    session::{
+        ConnectMode, Heartbeat,
+        machine::{Addressing, Connect, Due, Inbound, KeepAlive},
+    },
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+const READ_CHUNK: usize = 256;
+
+/// Connection settings of an [`IrsClient`], with the same defaults as
+/// [`Session`](crate::session::Session).
+#[derive(Debug, Clone)]
+pub struct IrsClientConfig {
+    mode: ConnectMode,
+    client_id: u32,
+    sender: DeviceCode,
+    receiver: DeviceCode,
+    accept_code: u8,
+    keep_alive: Duration,
+    heartbeat: Option<Heartbeat>,
+    timeout: Duration,
+    registry: Option<ParamRegistry>,
+    #[cfg(feature = "key-exchange")]
+    key_exchange: Option<KeyExchange>,
+}
+
+impl Default for IrsClientConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IrsClientConfig {
+    pub fn new() -> Self {
+        let defaults = VarHeader::new();
+        IrsClientConfig {
+            mode: ConnectMode::default(),
+            client_id: defaults.client_id.unwrap_or_default(),
+            sender: defaults.sender.unwrap_or(DeviceCode::Unknown(0)),
+            receiver: defaults.receiver.unwrap_or(DeviceCode::Unknown(0)),
+            accept_code: defaults.connect_return_code.unwrap_or_default(),
+            keep_alive: Duration::ZERO,
+            heartbeat: None,
+            timeout: DEFAULT_TIMEOUT,
+            registry: None,
+            #[cfg(feature = "key-exchange")]
+            key_exchange: None,
+        }
+    }
+    pub fn with_mode(mut self, mode: ConnectMode) -> Self {
+        self.mode = mode;
+        self
+    }
+    pub fn with_client_id(mut self, v: u32) -> Self {
+        self.client_id = v;
+        self
+    }
+    pub fn with_sender(mut self, v: DeviceCode) -> Self {
+        self.sender = v;
+        self
+    }
+    pub fn with_receiver(mut self, v: DeviceCode) -> Self {
+        self.receiver = v;
+        self
+    }
+    /// `connect_return_code` that means the connect was accepted, see
+    /// [`Session::with_accept_code`](crate::session::Session::with_accept_code).
+    pub fn with_accept_code(mut self, code: u8) -> Self {
+        self.accept_code = code;
+        self
+    }
+    /// Keep-alive interval to propose on connect, whole seconds up to
+    /// `u16::MAX`; zero disables keep-alive.
+    pub fn with_keep_alive(mut self, interval: Duration) -> Self {
+        self.keep_alive = Duration::from_secs(interval.as_secs().min(u16::MAX.into()));
+        self
+    }
+    /// Frame to send when idle and to consume on receipt, see [`Heartbeat`].
+    pub fn with_heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+        self.heartbeat = Some(heartbeat);
+        self
+    }
+    /// How long the handshake and each request wait for their reply.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// Registry to decode parameters and pair requests with responses with.
+    pub fn with_registry(mut self, registry: ParamRegistry) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+    /// Agree on a session key while connecting, see
+    /// [`Session::with_key_exchange`](crate::session::Session::with_key_exchange).
+    #[cfg(feature = "key-exchange")]
+    pub fn with_key_exchange(mut self, key_exchange: KeyExchange) -> Self {
+        self.key_exchange = Some(key_exchange);
+        self
+    }
+
+    /// Perform the client side of the handshake over `io` and start serving
+    /// requests. Must be called within a tokio runtime.
+    pub async fn connect<T>(self, io: T) -> Result<IrsClient, IrsError>
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (mut rd, wr) = tokio::io::split(io);
+        let registry = self
+            .registry
+            .unwrap_or_else(|| ParamRegistry::global().clone());
+        let mut decoder = FrameDecoder::new().with_registry(registry.clone());
+        let shared = Arc::new(Shared {
+            table: Mutex::new(Table {
+                requests: Requests::default(),
+                next_token: 0,
+                keep_alive: KeepAlive::new(self.keep_alive, now()),
+                ended: None,
+            }),
+            registry,
+            heartbeat: self.heartbeat,
+        });
+        let mut writer = Writer {
+            io: Box::new(wr),
+            addressing: Addressing {
+                client_id: self.client_id,
+                sender: self.sender,
+                receiver: self.receiver,
+            },
+            cipher: None,
+            shared: shared.clone(),
+        };
+
+        let (handshake, request) = Connect::start(
+            &writer.addressing,
+            self.mode,
+            self.accept_code,
+            self.keep_alive,
+            #[cfg(feature = "key-exchange")]
+            self.key_exchange.as_ref(),
+        )?;
+        writer.write(&request).await?;
+        let ack = time::timeout(self.timeout, read_msg(&mut rd, &mut decoder))
+            .await
+            .map_err(|_| IrsError::Timeout)??;
+        shared.received();
+        if let Some(cipher) = handshake.finish(&ack, &mut writer.addressing)? {
+            let cipher = Arc::new(cipher);
+            decoder.set_cipher(Some(cipher.clone()));
+            writer.cipher = Some(cipher);
+        }
+
+        let addressing = writer.addressing;
+        let (unsolicited_tx, unsolicited_rx) = mpsc::unbounded_channel();
+        let reader = Reader {
+            io: rd,
+            decoder,
+            shared: shared.clone(),
+            addressing,
+            unsolicited: unsolicited_tx,
+        };
+        let reader = tokio::spawn(reader.run());
+        let writer = Arc::new(tokio::sync::Mutex::new(writer));
+        let keep_alive = shared.keep_alive_every().map(|every| {
+            tokio::spawn(keep_alive(
+                writer.clone(),
+                shared.clone(),
+                reader.abort_handle(),
+                every,
+            ))
+        });
+        Ok(IrsClient {
+            shared,
+            writer,
+            unsolicited: tokio::sync::Mutex::new(unsolicited_rx),
+            reader,
+            keep_alive,
+            mode: self.mode,
+            client_id: addressing.client_id,
+            timeout: self.timeout,
+        })
+    }
+}
+
+/// Asynchronous request/response client, created with
+/// [`IrsClientConfig::connect`].
+///
+/// Responses are matched to requests the way [`Client`](crate::client::Client)
+/// does it: by msg_id and parameter pairing. Frames answering nothing are
+/// queued for [`IrsClient::recv_unsolicited`]. Dropping the
+/// client stops its background tasks without disconnecting.
+pub struct IrsClient {
+    shared: Arc<Shared>,
+    writer: Arc<tokio::sync::Mutex<Writer>>,
+    unsolicited: tokio::sync::Mutex<mpsc::UnboundedReceiver<Msg>>,
+    reader: JoinHandle<()>,
+    keep_alive: Option<JoinHandle<()>>,
+    mode: ConnectMode,
+    client_id: u32,
+    timeout: Duration,
+}
+
+impl IrsClient {
+    /// Client ID the board settled on.
+    pub fn client_id(&self) -> u32 {
+        self.client_id
+    }
+    /// Number of requests waiting for a response.
+    pub fn pending(&self) -> usize {
+        self.shared.table.lock().unwrap().requests.len()
+    }
+
+    /// Send `param` alone and return the parameter paired with it in the
+    /// registry from the response. Fails with [`IrsError::MissingParam`] if
+    /// the response carries none, e.g. because no parameter is registered as
+    /// answering `param`; use [`IrsClient::request_msg`] for those.
+    pub async fn request(&self, param: Param) -> Result<Param, IrsError> {
+        let id = param.id;
+        let mut msg = Msg::new();
+        msg.add_param(param);
+        let mut response = self.request_msg(msg).await?;
+        let params = &mut response.payload_mut().params;
+        let idx = params
+            .iter()
+            .position(|p| self.shared.registry.get(p.id).and_then(|i| i.response_to) == Some(id))
+            .ok_or(IrsError::MissingParam(id))?;
+        Ok(params.swap_remove(idx))
+    }
+
+    /// Send `msg` with a freshly allocated msg_id and wait for its response.
+    pub async fn request_msg(&self, mut msg: Msg) -> Result<Msg, IrsError> {
+        let (tx, rx) = oneshot::channel();
+        let (msg_id, token) = self.shared.register(&mut msg, tx)?;
+        // forgets the request however this future ends, even if dropped
+        let _guard = Forget {
+            shared: &self.shared,
+            token,
+            quarantine: self.timeout,
+        };
+        self.writer.lock().await.send_data(msg).await?;
+        match time::timeout(self.timeout, rx).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(_)) => Err(self.shared.ended()),
+            Err(_) => {
+                debug!("request {msg_id} timed out");
+                Err(IrsError::Timeout)
+            }
+        }
+    }
+
+    /// Next frame that did not answer a request.
+    pub async fn recv_unsolicited(&self) -> Result<Msg, IrsError> {
+        self.unsolicited
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| self.shared.ended())
+    }
+
+    /// Orderly disconnect.
+    pub async fn disconnect(self) -> Result<(), IrsError> {
+        let mut writer = self.writer.lock().await;
+        let msg = writer.addressing.frame(self.mode.disconnect_type());
+        writer.write(&msg).await
+    }
+}
+
+impl Drop for IrsClient {
+    fn drop(&mut self) {
+        self.reader.abort();
+        if let Some(keep_alive) = &self.keep_alive {
+            keep_alive.abort();
+        }
+    }
+}
+
+/// What [`IrsClient`] keeps about an outstanding request.
+struct Waiter {
+    /// Tells this request from a later one given the same msg_id.
+    token: u64,
+    tx: oneshot::Sender<Msg>,
+}
+
+struct Table {
+    requests: Requests<Waiter>,
+    next_token: u64,
+    keep_alive: KeepAlive,
+    /// Why the reader stopped, once it has.
+    ended: Option<IrsError>,
+}
+
+struct Shared {
+    table: Mutex<Table>,
+    registry: ParamRegistry,
+    heartbeat: Option<Heartbeat>,
+}
+
+impl Shared {
+    /// File `msg` as a request, giving it a msg_id; returns that and the
+    /// token to forget it by.
+    fn register(&self, msg: &mut Msg, tx: oneshot::Sender<Msg>) -> Result<(u8, u64), IrsError> {
+        let mut table = self.table.lock().unwrap();
+        if table.ended.is_some() {
+            return Err(Self::ended_error(&table));
+        }
+        let token = table.next_token;
+        let msg_id = table
+            .requests
+            .insert(msg, &self.registry, Waiter { token, tx }, now())?;
+        table.next_token += 1;
+        Ok((msg_id, token))
+    }
+
+    /// Abandon the request filed under `token`, if still unanswered; its
+    /// msg_id stays unused until `until`.
+    fn forget(&self, token: u64, until: Instant) {
+        let mut table = self.table.lock().unwrap();
+        table.requests.abandon_if(|r| r.slot.token == token, until);
+    }
+
+    /// Hand `msg` to the request it answers, or give it back.
+    fn dispatch(&self, msg: Msg) -> Option<Msg> {
+        let mut table = self.table.lock().unwrap();
+        match table.requests.answered_by(&msg, |_| true) {
+            // a request dropped meanwhile leaves nobody to take it
+            Some(idx) => table.requests.remove(idx).slot.tx.send(msg).err(),
+            None => Some(msg),
+        }
+    }
+
+    fn received(&self) {
+        self.table.lock().unwrap().keep_alive.received(now());
+    }
+
+    fn sent(&self) {
+        self.table.lock().unwrap().keep_alive.sent(now());
+    }
+
+    fn keep_alive_every(&self) -> Option<Duration> {
+        self.table.lock().unwrap().keep_alive.check_every()
+    }
+
+    /// Record why the session ended, unless it already has.
+    fn end(&self, reason: IrsError) {
+        let mut table = self.table.lock().unwrap();
+        if table.ended.is_some() {
+            return;
+        }
+        debug!("session ended: {reason}");
+        table.ended = Some(reason);
+        // dropping the senders wakes every waiting request
+        table.requests = Requests::default();
+    }
+
+    fn ended(&self) -> IrsError {
+        Self::ended_error(&self.table.lock().unwrap())
+    }
+
+    fn ended_error(table: &Table) -> IrsError {
+        match table.ended {
+            Some(IrsError::KeepAliveExpired) => IrsError::KeepAliveExpired,
+            _ => IrsError::Closed,
+        }
+    }
+}
+
+/// Forgets a request when its future completes or is dropped.
+struct Forget<'a> {
+    shared: &'a Shared,
+    token: u64,
+    /// How long the msg_id of an unanswered request stays unused.
+    quarantine: Duration,
+}
+
+impl Drop for Forget<'_> {
+    fn drop(&mut self) {
+        self.shared.forget(self.token, now() + self.quarantine);
+    }
+}
+
+struct Writer {
+    io: Box<dyn AsyncWrite + Send + Unpin>,
+    addressing: Addressing,
+    cipher: Option<Arc<SessionCipher>>,
+    shared: Arc<Shared>,
+}
+
+impl Writer {
+    async fn send_data(&mut self, mut msg: Msg) -> Result<(), IrsError> {
+        msg.set_message_type(MsgType::Data);
+        self.addressing.stamp(&mut msg);
+        self.write(&msg).await
+    }
+
+    async fn write(&mut self, msg: &Msg) -> Result<(), IrsError> {
+        let bytes = match &self.cipher {
+            Some(cipher) => msg.to_bytes_sealed(cipher)?,
+            None => msg.to_bytes()?,
+        };
+        debug!("send {} bytes", bytes.len());
+        self.io.write_all(&bytes).await?;
+        self.io.flush().await?;
+        self.shared.sent();
+        Ok(())
+    }
+}
+
+struct Reader<R> {
+    io: ReadHalf<R>,
+    decoder: FrameDecoder,
+    shared: Arc<Shared>,
+    addressing: Addressing,
+    unsolicited: mpsc::UnboundedSender<Msg>,
+}
+
+impl<R: AsyncRead> Reader<R> {
+    async fn run(mut self) {
+        let reason = self.read_loop().await;
+        self.shared.end(reason);
+    }
+
+    /// Read and file frames until the link ends, returning why it did.
+    async fn read_loop(&mut self) -> IrsError {
+        let mut buf = [0u8; READ_CHUNK];
+        loop {
+            match self.io.read(&mut buf).await {
+                Ok(0) => return IrsError::Closed,
+                Ok(n) => self.decoder.push(&buf[..n]),
+                Err(e) => return e.into(),
+            }
+            while let Some(msg) = self.decoder.next_msg() {
+                self.shared.received();
+                if let Err(reason) = self.file(msg) {
+                    return reason;
+                }
+            }
+        }
+    }
+
+    fn file(&mut self, msg: Msg) -> Result<(), IrsError> {
+        match self
+            .addressing
+            .classify(msg, self.shared.heartbeat.as_ref())
+        {
+            Inbound::Data(msg) => {
+                if let Some(msg) = self.shared.dispatch(msg) {
+                    debug!("unsolicited message {}", msg.get_msg_id());
+                    let _ = self.unsolicited.send(msg);
+                }
+                Ok(())
+            }
+            Inbound::Heartbeat | Inbound::Foreign => Ok(()),
+            Inbound::Disconnect => Err(IrsError::Closed),
+            Inbound::Unexpected(other) => {
+                debug!("dropping unexpected {other:?}");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Run the keep-alive timers, see [`KeepAlive`]: send the heartbeat, if
+/// any, and stop
+/// the reader once the peer has gone quiet.
+async fn keep_alive(
+    writer: Arc<tokio::sync::Mutex<Writer>>,
+    shared: Arc<Shared>,
+    reader: AbortHandle,
+    every: Duration,
+) {
+    let mut interval = time::interval(every);
+    loop {
+        interval.tick().await;
+        let due = {
+            let table = shared.table.lock().unwrap();
+            if table.ended.is_some() {
+                return;
+            }
+            table.keep_alive.due(now())
+        };
+        match due {
+            Due::Nothing => {}
+            Due::Heartbeat => {
+                let Some(heartbeat) = &shared.heartbeat else {
+                    continue;
+                };
+                let mut writer = writer.lock().await;
+                let msg = heartbeat.frame(&writer.addressing);
+                if writer.write(&msg).await.is_err() {
+                    return;
+                }
+            }
+            Due::Expired => {
+                debug!("keep-alive expired");
+                shared.end(IrsError::KeepAliveExpired);
+                reader.abort();
+                return;
+            }
+        }
+    }
+}
+
+/// The current time by tokio's clock, which tests can pause.
+fn now() -> Instant {
+    time::Instant::now().into_std()
+}
+
+async fn read_msg<R: AsyncRead + Unpin>(
+    io: &mut R,
+    decoder: &mut FrameDecoder,
+) -> Result<Msg, IrsError> {
+    let mut buf = [0u8; READ_CHUNK];
+    loop {
+        if let Some(msg) = decoder.next_msg() {
+            return Ok(msg);
+        }
+        match io.read(&mut buf).await? {
+            0 => return Err(IrsError::Closed),
+            n => decoder.push(&buf[..n]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::DuplexStream;
+
+    use super::*;
+    use crate::msg::params::{
+        ParamPayload,
+        data::{Param470, Param471},
+    };
+
+    /// Board end of a duplex stream: answers the connect, then yields frames.
+    struct Board {
+        io: DuplexStream,
+        decoder: FrameDecoder,
+    }
+
+    impl Board {
+        async fn accept(io: DuplexStream) -> Self {
+            let mut board = Board {
+                io,
+                decoder: FrameDecoder::new(),
+            };
+            let connect = board.recv().await;
+            assert_eq!(connect.get_message_type(), MsgType::ConnectExtended);
+            let mut ack = Msg::new();
+            ack.set_message_type(MsgType::ConnectExtendedAck);
+            ack.set_client_id(9);
+            board.send(&ack).await;
+            board
+        }
+        async fn recv(&mut self) -> Msg {
+            read_msg(&mut self.io, &mut self.decoder).await.unwrap()
+        }
+        async fn send(&mut self, msg: &Msg) {
+            self.io.write_all(&msg.to_bytes().unwrap()).await.unwrap();
+        }
+    }
+
+    fn response(msg_id: u8, height: u8) -> Msg {
+        let mut msg = Msg::new();
+        msg.set_msg_id(msg_id);
+        msg.set_client_id(9);
+        msg.add_param(Param::of(Param471 {
+            return_code: 0,
+            default_cutting_height: 30,
+            current_cutting_height: height,
+            information: 0,
+        }));
+        msg
+    }
+
+    async fn connected() -> (IrsClient, Board) {
+        let (a, b) = tokio::io::duplex(1024);
+        let config = IrsClientConfig::new().with_timeout(Duration::from_millis(200));
+        let (client, board) = tokio::join!(config.connect(a), Board::accept(b));
+        (client.unwrap(), board)
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests() {
+        let (client, mut board) = connected().await;
+        assert_eq!(client.client_id(), 9);
+        let serve = async {
+            let first = board.recv().await;
+            let second = board.recv().await;
+            // answer out of order, each with its own msg_id as height
+            for req in [second, first] {
+                let msg_id = req.get_msg_id();
+                board.send(&response(msg_id, msg_id)).await;
+            }
+        };
+        let height = |param: Param| param.get::<Param471>().unwrap().current_cutting_height;
+        let (a, b, ()) = tokio::join!(
+            client.request(Param::of(Param470)),
+            client.request(Param::of(Param470)),
+            serve
+        );
+        assert_eq!(height(a.unwrap()), 0);
+        assert_eq!(height(b.unwrap()), 1);
+        assert_eq!(client.pending(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_request_without_paired_response() {
+        let (client, mut board) = connected().await;
+        let vendor = Param::new(0x7123, ParamPayload::raw(0x7123, [1]));
+        // any response answers a request nothing is paired with, but none of
+        // its parameters is the answer
+        let (result, ()) = tokio::join!(client.request(vendor), async {
+            let req = board.recv().await;
+            board.send(&response(req.get_msg_id(), 40)).await;
+        });
+        assert!(matches!(result, Err(IrsError::MissingParam(0x7123))));
+        assert_eq!(client.pending(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keep_alive() {
+        let (a, b) = tokio::io::duplex(1024);
+        let heartbeat = Heartbeat::new(0x7fff, []);
+        let config = IrsClientConfig::new()
+            .with_keep_alive(Duration::from_secs(10))
+            .with_heartbeat(heartbeat.clone());
+        let (client, mut board) = tokio::join!(config.connect(a), Board::accept(b));
+        let client = client.unwrap();
+        // quiet for half the interval, the client sends a heartbeat
+        let start = time::Instant::now();
+        assert!(heartbeat.matches(&board.recv().await));
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+        // and gives up on the board, silent for a whole one
+        assert!(matches!(
+            client.recv_unsolicited().await,
+            Err(IrsError::KeepAliveExpired)
+        ));
+        assert!(matches!(
+            client.request(Param::of(Param470)).await,
+            Err(IrsError::KeepAliveExpired)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_timeout_unsolicited_and_close() {
+        let (client, mut board) = connected().await;
+        let (result, _) = tokio::join!(client.request(Param::of(Param470)), board.recv());
+        assert!(matches!(result, Err(IrsError::Timeout)));
+        assert_eq!(client.pending(), 0);
+
+        // the late response answers nothing any more, not even a newer
+        // request expecting the same parameter
+        let (result, _) = tokio::join!(client.request(Param::of(Param470)), async {
+            board.recv().await;
+            board.send(&response(0, 50)).await;
+        });
+        assert!(matches!(result, Err(IrsError::Timeout)));
+        let late = client.recv_unsolicited().await.unwrap();
+        assert_eq!(late.get_msg_id(), 0);
+
+        drop(board);
+        assert!(matches!(
+            client.recv_unsolicited().await,
+            Err(IrsError::Closed)
+        ));
+        assert!(matches!(
+            client.request(Param::of(Param470)).await,
+            Err(IrsError::Closed)
+        ));
+    }
+}
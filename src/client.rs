@@ -0,0 +1,405 @@
+pub(crate) mod pending;
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use tracing::debug;
+
+use crate::{
+    error::IrsError,
+    msg::{
+        Msg,
+        params::{
+            IrsParam, Param, ParamRegistry,
+            data::{Param470, Param471},
+        },
+    },
+    session::Session,
+    transport::Transport,
+};
+use pending::Requests;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What [`Client`] keeps about an outstanding request.
+struct Waiting {
+    deadline: Instant,
+    response: Option<Msg>,
+}
+
+/// Request/response layer over a connected [`Session`].
+///
+/// Each request gets the next free msg_id (wrapping after 255) and is kept in
+/// a table until its response arrives or its timeout passes. A response is
+/// matched to the request with the same msg_id, provided it carries one of
+/// the response parameters paired with the request's (e.g. 471 for 470).
+/// Frames that match nothing, such as late responses to requests that timed
+/// out, are queued for [`Client::recv_unsolicited`]; the msg_id of a request
+/// that timed out is only reused once another timeout has passed, so its
+/// late response cannot answer a newer request. Request/response pairs come from the
+/// [`ParamRegistry`], the global one unless set with
+/// [`Client::with_registry`].
+pub struct Client<T> {
+    session: Session<T>,
+    registry: Option<ParamRegistry>,
+    requests: Requests<Waiting>,
+    unsolicited: VecDeque<Msg>,
+    timeout: Duration,
+}
+
+impl<T: Transport> Client<T> {
+    pub fn new(session: Session<T>) -> Self {
+        Client {
+            session,
+            registry: None,
+            requests: Requests::default(),
+            unsolicited: VecDeque::new(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+    /// How long a request waits for its response.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// Registry to pair request and response parameters with.
+    pub fn with_registry(mut self, registry: ParamRegistry) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+    pub fn registry(&self) -> &ParamRegistry {
+        self.registry.as_ref().unwrap_or(ParamRegistry::global())
+    }
+
+    pub fn session(&self) -> &Session<T> {
+        &self.session
+    }
+    pub fn session_mut(&mut self) -> &mut Session<T> {
+        &mut self.session
+    }
+    pub fn into_inner(self) -> Session<T> {
+        self.session
+    }
+    /// Number of requests still waiting for a response.
+    pub fn pending(&self) -> usize {
+        self.requests.len()
+    }
+
+    /// Send `msg` and wait for its response.
+    pub fn request(&mut self, msg: Msg) -> Result<Msg, IrsError> {
+        let msg_id = self.start(msg)?;
+        self.wait(msg_id)
+    }
+
+    /// Send `msg` with a freshly allocated msg_id without waiting; collect
+    /// the response later with [`Client::wait`].
+    pub fn start(&mut self, mut msg: Msg) -> Result<u8, IrsError> {
+        let registry = self.registry.as_ref().unwrap_or(ParamRegistry::global());
+        let now = Instant::now();
+        let waiting = Waiting {
+            deadline: now + self.timeout,
+            response: None,
+        };
+        let msg_id = self.requests.insert(&mut msg, registry, waiting, now)?;
+        if let Err(e) = self.session.send(msg) {
+            self.forget(msg_id);
+            return Err(e);
+        }
+        Ok(msg_id)
+    }
+
+    /// Wait for the response to the request sent as `msg_id`. Fails with
+    /// [`IrsError::Timeout`] once the request's deadline passes; either way
+    /// the request is no longer outstanding afterwards.
+    pub fn wait(&mut self, msg_id: u8) -> Result<Msg, IrsError> {
+        loop {
+            let idx = self
+                .requests
+                .position(msg_id)
+                .ok_or(IrsError::NotPending(msg_id))?;
+            let waiting = &mut self.requests.get_mut(idx).slot;
+            if let Some(msg) = waiting.response.take() {
+                self.requests.remove(idx);
+                return Ok(msg);
+            }
+            let now = Instant::now();
+            if now >= waiting.deadline {
+                debug!("request {msg_id} timed out");
+                self.requests.abandon(idx, now + self.timeout);
+                return Err(IrsError::Timeout);
+            }
+            let wait = waiting.deadline - now;
+            self.poll(wait)?;
+        }
+    }
+
+    /// Read the default and current cutting height. The response's
+    /// `return_code` is passed on as is, as its values are not confirmed
+    /// against DGE-RLM-0069 yet.
+    pub fn cutting_height(&mut self) -> Result<Param471, IrsError> {
+        self.command(Param470)
+    }
+
+    /// Send `request` alone, and pick `R` out of the response.
+    fn command<R: IrsParam + Clone>(&mut self, request: impl IrsParam) -> Result<R, IrsError> {
+        let mut msg = Msg::new();
+        msg.add_param(Param::of(request));
+        let response = self.request(msg)?;
+        let data = response
+            .payload()
+            .get_params()
+            .iter()
+            .find_map(|p| p.get::<R>())
+            .ok_or(IrsError::MissingParam(R::ID))?;
+        Ok(data.clone())
+    }
+
+    /// Next frame that did not answer an outstanding request.
+    pub fn recv_unsolicited(&mut self, timeout: Duration) -> Result<Msg, IrsError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(msg) = self.unsolicited.pop_front() {
+                return Ok(msg);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(IrsError::Timeout);
+            }
+            self.poll(deadline - now)?;
+        }
+    }
+
+    /// Forget requests whose deadline has passed, returning their msg_ids.
+    /// Only needed for requests sent with [`Client::start`] that are never
+    /// waited for.
+    pub fn expire(&mut self) -> Vec<u8> {
+        let now = Instant::now();
+        self.requests.abandon_if(
+            |r| r.slot.response.is_none() && now >= r.slot.deadline,
+            now + self.timeout,
+        )
+    }
+
+    /// Receive at most one frame and file it.
+    fn poll(&mut self, timeout: Duration) -> Result<(), IrsError> {
+        match self.session.recv_timeout(timeout) {
+            Ok(msg) => {
+                self.dispatch(msg);
+                Ok(())
+            }
+            Err(IrsError::Timeout) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn dispatch(&mut self, msg: Msg) {
+        let now = Instant::now();
+        let open = |w: &Waiting| w.response.is_none() && now < w.deadline;
+        match self.requests.answered_by(&msg, open) {
+            Some(idx) => self.requests.get_mut(idx).slot.response = Some(msg),
+            None => {
+                debug!("unsolicited message {}", msg.get_msg_id());
+                self.unsolicited.push_back(msg);
+            }
+        }
+    }
+
+    fn forget(&mut self, msg_id: u8) {
+        if let Some(idx) = self.requests.position(msg_id) {
+            self.requests.remove(idx);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::transport::{Framed, memory};
+
+    type MemorySession = Session<Framed<memory::MemoryLink>>;
+
+    fn connected_pair() -> (Client<Framed<memory::MemoryLink>>, MemorySession) {
+        let (a, b) = memory::pair();
+        let mut client = Session::new(a).with_timeout(Duration::from_millis(20));
+        let mut board = Session::new(b).with_timeout(Duration::from_millis(500));
+        thread::scope(|s| {
+            s.spawn(|| board.accept().unwrap());
+            client.connect().unwrap();
+        });
+        let client = Client::new(client).with_timeout(Duration::from_millis(500));
+        (client, board)
+    }
+
+    fn request() -> Msg {
+        let mut msg = Msg::new();
+        msg.add_param(Param::of(Param470));
+        msg
+    }
+
+    fn response(msg_id: u8, height: u8) -> Msg {
+        let mut msg = Msg::new();
+        msg.set_msg_id(msg_id);
+        msg.add_param(Param::of(Param471 {
+            return_code: 0,
+            default_cutting_height: 30,
+            current_cutting_height: height,
+            information: 0,
+        }));
+        msg
+    }
+
+    fn height(msg: &Msg) -> u8 {
+        let data: &Param471 = msg.get_param(0).unwrap().get().unwrap();
+        data.current_cutting_height
+    }
+
+    #[test]
+    fn test_request_allocates_msg_ids() {
+        let (mut client, mut board) = connected_pair();
+        thread::scope(|s| {
+            s.spawn(|| {
+                for _ in 0..3 {
+                    let req = board.recv().unwrap();
+                    let msg_id = req.get_msg_id();
+                    board.send(response(msg_id, msg_id)).unwrap();
+                }
+            });
+            for n in 0..3 {
+                let resp = client.request(request()).unwrap();
+                assert_eq!(resp.get_msg_id(), n);
+                assert_eq!(height(&resp), n);
+            }
+        });
+        assert_eq!(client.pending(), 0);
+    }
+
+    #[test]
+    fn test_out_of_order_responses() {
+        let (mut client, mut board) = connected_pair();
+        let a = client.start(request()).unwrap();
+        let b = client.start(request()).unwrap();
+        assert_ne!(a, b);
+        board.send(response(b, 2)).unwrap();
+        board.send(response(a, 1)).unwrap();
+        assert_eq!(height(&client.wait(a).unwrap()), 1);
+        assert_eq!(height(&client.wait(b).unwrap()), 2);
+    }
+
+    #[test]
+    fn test_match_by_msg_id_only() {
+        let (client, mut board) = connected_pair();
+        let mut client = client.with_timeout(Duration::from_millis(50));
+        assert!(matches!(client.request(request()), Err(IrsError::Timeout)));
+        // a late response to the timed-out request must not answer a newer
+        // one expecting the same parameter
+        let newer = client.start(request()).unwrap();
+        assert_ne!(newer, 0);
+        board.send(response(0, 7)).unwrap();
+        assert!(matches!(client.wait(newer), Err(IrsError::Timeout)));
+        let late = client.recv_unsolicited(Duration::from_millis(500)).unwrap();
+        assert_eq!(late.get_msg_id(), 0);
+    }
+
+    #[test]
+    fn test_timeout_and_late_response() {
+        let (client, mut board) = connected_pair();
+        let mut client = client.with_timeout(Duration::from_millis(20));
+        assert!(matches!(client.request(request()), Err(IrsError::Timeout)));
+        assert_eq!(client.pending(), 0);
+        board.send(response(0, 7)).unwrap();
+        let late = client.recv_unsolicited(Duration::from_millis(500)).unwrap();
+        assert_eq!(height(&late), 7);
+        assert!(matches!(client.wait(0), Err(IrsError::NotPending(0))));
+    }
+
+    #[test]
+    fn test_msg_id_wraparound() {
+        let (client, _board) = connected_pair();
+        let mut client = client.with_timeout(Duration::from_millis(20));
+        for n in 0..=u8::MAX {
+            assert_eq!(client.start(request()).unwrap(), n);
+        }
+        assert!(matches!(
+            client.start(request()),
+            Err(IrsError::MsgIdsExhausted)
+        ));
+        assert!(matches!(client.wait(3), Err(IrsError::Timeout)));
+        // only 3 is free again, once it has been quarantined for a timeout
+        assert!(matches!(
+            client.start(request()),
+            Err(IrsError::MsgIdsExhausted)
+        ));
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(client.start(request()).unwrap(), 3);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(client.expire().len(), 256);
+        assert!(matches!(
+            client.start(request()),
+            Err(IrsError::MsgIdsExhausted)
+        ));
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(client.start(request()).unwrap(), 4);
+    }
+
+    /// Answer one request on `board` with `reply`, echoing its msg_id.
+    fn serve_one(board: &mut MemorySession, reply: impl IrsParam) -> Msg {
+        let req = board.recv().unwrap();
+        let mut msg = Msg::new();
+        msg.set_msg_id(req.get_msg_id());
+        msg.add_param(Param::of(reply));
+        board.send(msg).unwrap();
+        req
+    }
+
+    #[test]
+    fn test_cutting_height() {
+        let (mut client, mut board) = connected_pair();
+        thread::scope(|s| {
+            s.spawn(|| {
+                let req = serve_one(
+                    &mut board,
+                    Param471 {
+                        return_code: 0,
+                        default_cutting_height: 30,
+                        current_cutting_height: 35,
+                        information: 0,
+                    },
+                );
+                assert!(req.get_param(0).unwrap().get::<Param470>().is_some());
+            });
+            let resp = client.cutting_height().unwrap();
+            assert_eq!(resp.default_cutting_height, 30);
+            assert_eq!(resp.current_cutting_height, 35);
+        });
+    }
+
+    #[test]
+    fn test_cutting_height_missing() {
+        let (client, mut board) = connected_pair();
+        // without pairing info any reply with the right msg_id is accepted
+        let mut client = client.with_registry(ParamRegistry::new());
+        thread::scope(|s| {
+            s.spawn(|| {
+                serve_one(
+                    &mut board,
+                    Param471 {
+                        return_code: 2,
+                        default_cutting_height: 30,
+                        current_cutting_height: 30,
+                        information: 0,
+                    },
+                );
+                serve_one(&mut board, Param470);
+            });
+            assert_eq!(client.cutting_height().unwrap().return_code, 2);
+            assert!(matches!(
+                client.cutting_height(),
+                Err(IrsError::MissingParam(471))
+            ));
+        });
+    }
+}
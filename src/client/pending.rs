@@ -0,0 +1,182 @@
+//! Outstanding requests and the matching of responses to them, shared by
+//! [`Client`](super::Client) and
+//! [`IrsClient`](crate::async_client::IrsClient).
+
+use std::time::Instant;
+
+use crate::{
+    error::IrsError,
+    msg::{Msg, params::ParamRegistry},
+};
+
+/// A request waiting for its response, with whatever the client keeps
+/// about it in `slot`.
+pub(crate) struct Request<S> {
+    pub msg_id: u8,
+    /// Parameter IDs the response may carry, from the request's parameters.
+    expects: Vec<u16>,
+    pub slot: S,
+}
+
+impl<S> Request<S> {
+    fn answered_by(&self, param_ids: &[u16]) -> bool {
+        self.expects.is_empty() || param_ids.iter().any(|id| self.expects.contains(id))
+    }
+}
+
+/// Each request gets the next free msg_id, wrapping after 255. A response
+/// answers the request with the same msg_id, provided it carries one of the
+/// response parameters paired with the request's (e.g. 471 for 470).
+///
+/// The msg_id of a request abandoned unanswered, e.g. on timeout, is not
+/// handed out again for a while, so that a late response to it cannot answer
+/// a newer request.
+pub(crate) struct Requests<S> {
+    next_msg_id: u8,
+    pending: Vec<Request<S>>,
+    /// Abandoned msg_ids and until when they stay unused.
+    quarantine: Vec<(u8, Instant)>,
+}
+
+impl<S> Default for Requests<S> {
+    fn default() -> Self {
+        Requests {
+            next_msg_id: 0,
+            pending: Vec::new(),
+            quarantine: Vec::new(),
+        }
+    }
+}
+
+impl<S> Requests<S> {
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Give `msg` the next free msg_id and file it with `slot`; `registry`
+    /// tells which parameters answer it.
+    pub fn insert(
+        &mut self,
+        msg: &mut Msg,
+        registry: &ParamRegistry,
+        slot: S,
+        now: Instant,
+    ) -> Result<u8, IrsError> {
+        self.quarantine.retain(|(_, until)| now < *until);
+        let msg_id = (0..=u8::MAX)
+            .map(|i| self.next_msg_id.wrapping_add(i))
+            .find(|id| self.position(*id).is_none() && !self.quarantined(*id))
+            .ok_or(IrsError::MsgIdsExhausted)?;
+        self.next_msg_id = msg_id.wrapping_add(1);
+        msg.set_msg_id(msg_id);
+        let expects = msg
+            .payload()
+            .get_params()
+            .iter()
+            .flat_map(|p| registry.responses_to(p.id))
+            .collect();
+        self.pending.push(Request {
+            msg_id,
+            expects,
+            slot,
+        });
+        Ok(msg_id)
+    }
+
+    fn quarantined(&self, msg_id: u8) -> bool {
+        self.quarantine.iter().any(|(id, _)| *id == msg_id)
+    }
+
+    pub fn position(&self, msg_id: u8) -> Option<usize> {
+        self.pending.iter().position(|r| r.msg_id == msg_id)
+    }
+
+    /// The request `msg` answers, among those `open` says still wait.
+    pub fn answered_by(&self, msg: &Msg, open: impl Fn(&S) -> bool) -> Option<usize> {
+        let msg_id = msg.get_msg_id();
+        let param_ids: Vec<u16> = msg.payload().get_params().iter().map(|p| p.id).collect();
+        self.pending
+            .iter()
+            .position(|r| open(&r.slot) && r.msg_id == msg_id && r.answered_by(&param_ids))
+    }
+
+    pub fn get_mut(&mut self, idx: usize) -> &mut Request<S> {
+        &mut self.pending[idx]
+    }
+
+    pub fn remove(&mut self, idx: usize) -> Request<S> {
+        self.pending.remove(idx)
+    }
+
+    /// Remove the request at `idx` unanswered; its msg_id stays unused
+    /// until `until`.
+    pub fn abandon(&mut self, idx: usize, until: Instant) -> Request<S> {
+        let request = self.pending.remove(idx);
+        self.quarantine.push((request.msg_id, until));
+        request
+    }
+
+    /// [`Requests::abandon`] every request `abandon` picks, returning their
+    /// msg_ids.
+    pub fn abandon_if(
+        &mut self,
+        mut abandon: impl FnMut(&Request<S>) -> bool,
+        until: Instant,
+    ) -> Vec<u8> {
+        let mut abandoned = Vec::new();
+        self.pending.retain(|r| {
+            let drop = abandon(r);
+            if drop {
+                abandoned.push(r.msg_id);
+            }
+            !drop
+        });
+        self.quarantine
+            .extend(abandoned.iter().map(|&msg_id| (msg_id, until)));
+        abandoned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::msg::params::{Param, data::Param470};
+
+    fn request() -> Msg {
+        let mut msg = Msg::new();
+        msg.add_param(Param::of(Param470));
+        msg
+    }
+
+    #[test]
+    fn test_abandoned_ids_quarantined_across_wraparound() {
+        let registry = ParamRegistry::global();
+        let start = Instant::now();
+        let mut requests = Requests::default();
+        let insert = |requests: &mut Requests<()>, now| {
+            requests.insert(&mut request(), registry, (), now).unwrap()
+        };
+        assert_eq!(insert(&mut requests, start), 0);
+        let idx = requests.position(0).unwrap();
+        requests.abandon(idx, start + Duration::from_secs(1));
+        // wrapping around, the abandoned 0 is skipped while quarantined
+        for expected in 1..=u8::MAX {
+            let msg_id = insert(&mut requests, start);
+            assert_eq!(msg_id, expected);
+            requests.remove(requests.position(msg_id).unwrap());
+        }
+        assert_eq!(insert(&mut requests, start), 1);
+        requests.remove(requests.position(1).unwrap());
+        // and handed out again once the quarantine is over
+        let later = start + Duration::from_secs(1);
+        assert_eq!(insert(&mut requests, later), 2);
+        requests.remove(requests.position(2).unwrap());
+        for _ in 3..=u8::MAX {
+            let msg_id = insert(&mut requests, later);
+            requests.remove(requests.position(msg_id).unwrap());
+        }
+        assert_eq!(insert(&mut requests, later), 0);
+    }
+}
@@ -0,0 +1,232 @@
+use std::fmt;
+
+use crate::msg::{header::MsgType, serialization::BinarySerializeError};
+
+/// Errors produced while decoding IRS frames or moving them over a transport.
+///
+/// Every decode variant carries the byte `offset` of the offending data,
+/// counted from the start of the buffer handed to the outermost decode call,
+/// so a failure can be matched against a capture.
+#[derive(Debug)]
+pub enum IrsError {
+    /// The input ended before `needed` bytes were available.
+    Truncated {
+        offset: usize,
+        needed: usize,
+        available: usize,
+    },
+    /// First byte of the frame is not SOH (0x01).
+    BadSoh {
+        offset: usize,
+        found: u8,
+    },
+    /// Second byte of the frame is not STX (0x02).
+    BadStx {
+        offset: usize,
+        found: u8,
+    },
+    UnknownMsgType {
+        offset: usize,
+        value: u8,
+    },
+    /// A length field disagrees with the number of bytes actually present.
+    LengthMismatch {
+        offset: usize,
+        expected: usize,
+        actual: usize,
+    },
+    CrcMismatch {
+        offset: usize,
+        expected: u16,
+        actual: u16,
+    },
+    UnknownParamId {
+        offset: usize,
+        id: u16,
+    },
+    PayloadDecode {
+        offset: usize,
+        source: BinarySerializeError,
+    },
+    /// A parameter's data could not be serialized.
+    PayloadEncode(BinarySerializeError),
+    /// Encoded data too long for the 16 bit length field announcing it.
+    TooLong {
+        field: &'static str,
+        len: usize,
+    },
+    Io(std::io::Error),
+    /// No complete frame arrived within the receive timeout.
+    Timeout,
+    /// The peer closed the link.
+    Closed,
+    /// Data exchange attempted before a successful connect handshake.
+    NotConnected,
+    /// The peer answered the connect with a return code other than the
+    /// accepting one; the raw `connect_return_code`.
+    ConnectRefused(u8),
+    /// A frame of a type that is not valid in the current session state.
+    UnexpectedMsg(MsgType),
+    /// Nothing was heard from the peer within the keep-alive interval.
+    KeepAliveExpired,
+    /// A var header field required by `msg_type` was not set when encoding.
+    MissingField {
+        msg_type: MsgType,
+        field: &'static str,
+    },
+    /// All 256 msg_ids are taken by outstanding requests.
+    MsgIdsExhausted,
+    /// No outstanding request was sent with this msg_id.
+    NotPending(u8),
+    /// The response lacks the parameter the request asked for.
+    MissingParam(u16),
+    /// The connect handshake did not yield a session key.
+    KeyExchange(&'static str),
+    /// An encrypted payload failed authentication, or lacks the sealed
+    /// section a session with a cipher requires.
+    Unauthenticated,
+    /// A sealed payload repeats, or is too old to tell from a repeat of,
+    /// this sequence number.
+    Replayed(u64),
+    /// The payload holds bytes sealed under another key and cannot be
+    /// sealed again.
+    AlreadySealed,
+}
+
+impl IrsError {
+    /// Byte offset of the error, if the error refers to a position in the input.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            IrsError::Truncated { offset, .. }
+            | IrsError::BadSoh { offset, .. }
+            | IrsError::BadStx { offset, .. }
+            | IrsError::UnknownMsgType { offset, .. }
+            | IrsError::LengthMismatch { offset, .. }
+            | IrsError::CrcMismatch { offset, .. }
+            | IrsError::UnknownParamId { offset, .. }
+            | IrsError::PayloadDecode { offset, .. } => Some(*offset),
+            _ => None,
+        }
+    }
+
+    /// Shift the offset by `base`, used when a sub-slice decoder reports back
+    /// to the caller that handed it the slice.
+    pub fn at(mut self, base: usize) -> Self {
+        match &mut self {
+            IrsError::Truncated { offset, .. }
+            | IrsError::BadSoh { offset, .. }
+            | IrsError::BadStx { offset, .. }
+            | IrsError::UnknownMsgType { offset, .. }
+            | IrsError::LengthMismatch { offset, .. }
+            | IrsError::CrcMismatch { offset, .. }
+            | IrsError::UnknownParamId { offset, .. }
+            | IrsError::PayloadDecode { offset, .. } => *offset += base,
+            _ => {}
+        }
+        self
+    }
+}
+
+impl fmt::Display for IrsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IrsError::Truncated {
+                offset,
+                needed,
+                available,
+            } => write!(
+                f,
+                "truncated input at offset {offset}: need {needed} bytes, have {available}"
+            ),
+            IrsError::BadSoh { offset, found } => {
+                write!(f, "bad SOH at offset {offset}: found {found:#04x}")
+            }
+            IrsError::BadStx { offset, found } => {
+                write!(f, "bad STX at offset {offset}: found {found:#04x}")
+            }
+            IrsError::UnknownMsgType { offset, value } => {
+                write!(f, "unknown message type {value} at offset {offset}")
+            }
+            IrsError::LengthMismatch {
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "length mismatch at offset {offset}: expected {expected}, got {actual}"
+            ),
+            IrsError::CrcMismatch {
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "crc mismatch at offset {offset}: expected {expected:#06x}, got {actual:#06x}"
+            ),
+            IrsError::UnknownParamId { offset, id } => {
+                write!(f, "unknown parameter id {id} at offset {offset}")
+            }
+            IrsError::PayloadDecode { offset, source } => {
+                write!(f, "payload decode failed at offset {offset}: {source}")
+            }
+            IrsError::PayloadEncode(source) => write!(f, "payload encode failed: {source}"),
+            IrsError::TooLong { field, len } => {
+                write!(f, "{field} of {len} bytes does not fit a 16 bit length")
+            }
+            IrsError::Io(e) => write!(f, "io error: {e}"),
+            IrsError::Timeout => write!(f, "timed out"),
+            IrsError::Closed => write!(f, "link closed by peer"),
+            IrsError::NotConnected => write!(f, "session not connected"),
+            IrsError::ConnectRefused(code) => write!(f, "connect refused with code {code:#04x}"),
+            IrsError::UnexpectedMsg(msg_type) => write!(f, "unexpected {msg_type:?} message"),
+            IrsError::KeepAliveExpired => write!(f, "peer silent for the keep-alive interval"),
+            IrsError::MissingField { msg_type, field } => {
+                write!(f, "{msg_type:?} var header is missing {field}")
+            }
+            IrsError::MsgIdsExhausted => write!(f, "no free msg_id for another request"),
+            IrsError::NotPending(msg_id) => write!(f, "no outstanding request {msg_id}"),
+            IrsError::MissingParam(id) => write!(f, "response lacks parameter {id}"),
+            IrsError::KeyExchange(reason) => write!(f, "key exchange failed: {reason}"),
+            IrsError::Unauthenticated => write!(f, "payload failed authentication"),
+            IrsError::Replayed(seq) => write!(f, "sequence number {seq} replayed"),
+            IrsError::AlreadySealed => write!(f, "payload is already sealed"),
+        }
+    }
+}
+
+impl std::error::Error for IrsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            IrsError::PayloadDecode { source, .. } | IrsError::PayloadEncode(source) => {
+                Some(source)
+            }
+            IrsError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for IrsError {
+    fn from(e: std::io::Error) -> Self {
+        IrsError::Io(e)
+    }
+}
+
+/// Fail with [`IrsError::Truncated`] unless `bytes` holds at least `needed` bytes.
+pub(crate) fn ensure_len(bytes: &[u8], needed: usize) -> Result<(), IrsError> {
+    if bytes.len() < needed {
+        Err(IrsError::Truncated {
+            offset: bytes.len(),
+            needed,
+            available: bytes.len(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// `len` as the value of a 16 bit length field, failing with
+/// [`IrsError::TooLong`] rather than truncating.
+pub(crate) fn len_u16(field: &'static str, len: usize) -> Result<u16, IrsError> {
+    u16::try_from(len).map_err(|_| IrsError::TooLong { field, len })
+}
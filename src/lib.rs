@@ -1,7 +1,28 @@
-mod msg;
+#[cfg(feature = "async")]
+pub mod async_client;
+pub mod client;
+pub mod clock;
+pub mod error;
+pub mod msg;
+pub mod router;
+pub mod session;
+pub mod transport;
+
+pub use error::IrsError;
+pub use msg::params::IrsParam;
+
+// lets `#[derive(IrsParam)]` output name this crate as `::irs_rs` from inside it too
+extern crate self as irs_rs;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use inventory;
+}
+
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
 }
+#[cfg(test)]
 fn init_tracing() {
     let _ = tracing_subscriber::fmt()
         .with_max_level(tracing::Level::DEBUG)
@@ -41,26 +62,22 @@ mod tests {
     fn test_param() {
         let param = msg::params::Param::new(
             471,
-            msg::params::ParamPayload::P471(data::Param471 {
+            msg::params::ParamPayload::new(data::Param471 {
                 return_code: 1,
                 current_cutting_height: 2,
                 default_cutting_height: 3,
                 information: 1,
             }),
         );
-        let bytes = param.to_bytes();
+        let bytes = param.to_bytes().unwrap();
         println!("param 471 bytes: {bytes:?}");
         let p1 = msg::params::Param::from_bytes(&bytes).unwrap();
         assert_eq!(p1.id, 471);
-        match p1.data {
-            msg::params::ParamPayload::P471(data) => {
-                assert_eq!(data.return_code, 1);
-                assert_eq!(data.current_cutting_height, 2);
-                assert_eq!(data.default_cutting_height, 3);
-                assert_eq!(data.information, 1);
-            }
-            _ => panic!("unexpected param payload"),
-        }
+        let data: &data::Param471 = p1.get().expect("unexpected param payload");
+        assert_eq!(data.return_code, 1);
+        assert_eq!(data.current_cutting_height, 2);
+        assert_eq!(data.default_cutting_height, 3);
+        assert_eq!(data.information, 1);
     }
     #[test]
     fn test_payload() {
@@ -70,20 +87,20 @@ mod tests {
         payload.unencrypted_length = 0;
         payload.add_param(msg::params::Param::new(
             470,
-            msg::params::ParamPayload::P470,
+            msg::params::ParamPayload::new(data::Param470),
         ));
         payload.add_param(msg::params::Param::new(
             471,
-            msg::params::ParamPayload::P471(data::Param471 {
+            msg::params::ParamPayload::new(data::Param471 {
                 return_code: 1,
                 current_cutting_height: 2,
                 default_cutting_height: 3,
                 information: 1,
             }),
         ));
-        let bytes = payload.to_bytes();
+        let bytes = payload.to_bytes().unwrap();
         println!("payload bytes: {bytes:?}");
-        let p1 = msg::payload::Payload::from_bytes(&bytes);
+        let p1 = msg::payload::Payload::from_bytes(&bytes).unwrap();
         assert_eq!(p1.msg_id, 5);
         assert_eq!(p1.params.len(), 2);
         let params = p1.get_params();
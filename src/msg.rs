@@ -1,21 +1,46 @@
+pub mod cipher;
+#[cfg(feature = "async")]
+pub mod codec;
+pub mod frame;
 pub mod header;
 pub mod params;
 pub mod payload;
 pub mod serialization;
 
-use header::MsgType;
-use params::Param;
+use header::{DeviceCode, MsgType};
+use params::{Param, ParamRegistry};
 
-use crate::msg::{
-    header::{Header, VarHeader},
-    payload::Payload,
+use crate::{
+    error::{IrsError, ensure_len, len_u16},
+    msg::{
+        cipher::SessionCipher,
+        header::{Header, VarHeader},
+        payload::Payload,
+    },
 };
 
+/// How [`Msg::from_bytes_with`] treats a header or payload CRC that does not
+/// match the received bytes.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum CrcCheck {
+    /// Reject the frame with [`IrsError::CrcMismatch`].
+    #[default]
+    Strict,
+    /// Decode the frame anyway and record the mismatches, see [`Msg::crc_errors`].
+    Lenient,
+}
+
 pub struct Msg {
     header: header::Header,
     var_header: header::VarHeader,
     payload: payload::Payload,
-    body: Vec<u8>,
+    crc_errors: Vec<IrsError>,
+}
+
+impl Default for Msg {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Msg {
@@ -24,7 +49,7 @@ impl Msg {
             header: Header::new(),
             var_header: VarHeader::new(),
             payload: Payload::new(),
-            body: Vec::new(),
+            crc_errors: Vec::new(),
         }
     }
     pub fn set_message_type(&mut self, msg_type: MsgType) {
@@ -36,8 +61,8 @@ impl Msg {
     pub fn add_param(&mut self, param: Param) {
         self.payload.add_param(param);
     }
-    pub fn get_param(&self, index: usize) -> &Param {
-        self.payload.get_params().get(index).unwrap()
+    pub fn get_param(&self, index: usize) -> Option<&Param> {
+        self.payload.get_params().get(index)
     }
     pub fn set_msg_id(&mut self, id: u8) {
         self.payload.msg_id = id;
@@ -48,35 +73,419 @@ impl Msg {
     pub fn set_client_id(&mut self, client_id: u32) {
         self.var_header.set_client_id(client_id);
     }
-    pub fn to_bytes(self) -> Vec<u8> {
+    pub fn with_sender(mut self, sender: DeviceCode) -> Self {
+        self.var_header.sender = Some(sender);
+        self
+    }
+    pub fn with_receiver(mut self, receiver: DeviceCode) -> Self {
+        self.var_header.receiver = Some(receiver);
+        self
+    }
+    pub fn sender(&self) -> Option<DeviceCode> {
+        self.var_header.sender
+    }
+    pub fn receiver(&self) -> Option<DeviceCode> {
+        self.var_header.receiver
+    }
+    /// CRC mismatches found while decoding with [`CrcCheck::Lenient`].
+    pub fn crc_errors(&self) -> &[IrsError] {
+        &self.crc_errors
+    }
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+    pub fn var_header(&self) -> &VarHeader {
+        &self.var_header
+    }
+    pub fn var_header_mut(&mut self) -> &mut VarHeader {
+        &mut self.var_header
+    }
+    pub fn payload(&self) -> &Payload {
+        &self.payload
+    }
+    pub fn payload_mut(&mut self) -> &mut Payload {
+        &mut self.payload
+    }
+    /// Encode the frame, filling in `payload_length` and the header and
+    /// payload CRCs. Fails if the var header lacks a field the message type
+    /// requires, or if a parameter does not encode or a length overflows.
+    ///
+    /// ```text
+    ///    |--------|------------|---------------------------|
+    ///    | Header | Var header | Payload                   |
+    ///    | 7 bytes| per type   | payload_length bytes      |
+    ///    |--------+------------+---------------------------|
+    /// ```
+    pub fn to_bytes(&self) -> Result<Vec<u8>, IrsError> {
+        self.encode(None)
+    }
+    /// Like [`Msg::to_bytes`], sealing the payload as the next frame
+    /// `cipher` sends, see [`Payload::to_bytes_sealed`]. The var header must
+    /// name the sender.
+    pub fn to_bytes_sealed(&self, cipher: &SessionCipher) -> Result<Vec<u8>, IrsError> {
+        self.encode(Some(cipher))
+    }
+    fn encode(&self, cipher: Option<&SessionCipher>) -> Result<Vec<u8>, IrsError> {
         let mut buf = Vec::new();
         let msg_type = self.header.msg_type;
-        let header_bytes: Vec<u8> = self.header.into();
+        let var_header = self.var_header.clone().build(msg_type)?;
+        let payload = match cipher {
+            Some(cipher) => {
+                let sender = self.var_header.sender.ok_or(IrsError::MissingField {
+                    msg_type,
+                    field: "sender",
+                })?;
+                self.payload
+                    .to_bytes_sealed(cipher, sender, &self.header, &var_header.data)?
+            }
+            None => self.payload.to_bytes()?,
+        };
+        let mut header = self.header.clone();
+        header.payload_length = len_u16("payload", payload.len())?;
+        header.calculate_crc(&var_header.data);
+        let header_bytes: Vec<u8> = header.into();
         buf.extend_from_slice(&header_bytes);
-        let var_header = self.var_header.build(msg_type);
         buf.extend_from_slice(&var_header.data);
-        let payload = self.payload.to_bytes();
         buf.extend_from_slice(&payload);
-        buf
+        Ok(buf)
     }
-    pub fn from_bytes(bytes: &[u8]) -> Self {
-        let header = Header::from(&bytes[0..8]);
-        let var_header = VarHeader::from_bytes(&bytes[8..], header.msg_type);
-        let var_head_len = VarHeader::default_size(header.msg_type);
-        let payload = Payload::from_bytes(&bytes[(7 + var_head_len.unwrap()) as usize..]);
-        Self {
+    /// Decode one frame, rejecting it on any CRC mismatch.
+    /// Error offsets are relative to the start of `bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, IrsError> {
+        Self::from_bytes_with(bytes, CrcCheck::Strict)
+    }
+    /// Decode one frame, handling CRC mismatches according to `crc_check`.
+    ///
+    /// `bytes` must hold exactly one frame: fewer bytes than the header's
+    /// `payload_length` announces is [`IrsError::Truncated`], more is
+    /// [`IrsError::LengthMismatch`].
+    pub fn from_bytes_with(bytes: &[u8], crc_check: CrcCheck) -> Result<Self, IrsError> {
+        Self::from_bytes_in(bytes, crc_check, ParamRegistry::global())
+    }
+    /// Like [`Msg::from_bytes_with`], decoding parameters with `registry`.
+    pub fn from_bytes_in(
+        bytes: &[u8],
+        crc_check: CrcCheck,
+        registry: &ParamRegistry,
+    ) -> Result<Self, IrsError> {
+        Self::decode(bytes, crc_check, registry, None)
+    }
+    /// Decode one frame of a session with `cipher`, rejecting it on any CRC
+    /// mismatch and opening its sealed section, see
+    /// [`Payload::from_bytes_sealed`].
+    pub fn from_bytes_sealed(
+        bytes: &[u8],
+        registry: &ParamRegistry,
+        cipher: &SessionCipher,
+    ) -> Result<Self, IrsError> {
+        Self::decode(bytes, CrcCheck::Strict, registry, Some(cipher))
+    }
+    fn decode(
+        bytes: &[u8],
+        crc_check: CrcCheck,
+        registry: &ParamRegistry,
+        cipher: Option<&SessionCipher>,
+    ) -> Result<Self, IrsError> {
+        let header = Header::try_from(bytes)?;
+        let var_header = VarHeader::from_bytes(&bytes[Header::SIZE..], header.msg_type)
+            .map_err(|e| e.at(Header::SIZE))?;
+        let payload_at = Header::SIZE + var_header.size as usize;
+        let frame_len = payload_at + header.payload_length as usize;
+        ensure_len(bytes, frame_len)?;
+        if bytes.len() > frame_len {
+            return Err(IrsError::LengthMismatch {
+                offset: frame_len,
+                expected: frame_len,
+                actual: bytes.len(),
+            });
+        }
+        let mut crc_errors = Vec::new();
+        let header_crc = Header::crc_over(
+            &bytes[..Header::CRC_OFFSET],
+            &bytes[Header::SIZE..payload_at],
+        );
+        if header_crc != header.crc {
+            crc_errors.push(IrsError::CrcMismatch {
+                offset: Header::CRC_OFFSET,
+                expected: header_crc,
+                actual: header.crc,
+            });
+        }
+        let payload_bytes = &bytes[payload_at..];
+        ensure_len(payload_bytes, Payload::MIN_SIZE).map_err(|e| e.at(payload_at))?;
+        let crc_at = bytes.len() - 2;
+        let payload_crc = Payload::crc_over(payload_bytes);
+        let crc = u16::from_le_bytes([bytes[crc_at], bytes[crc_at + 1]]);
+        if payload_crc != crc {
+            crc_errors.push(IrsError::CrcMismatch {
+                offset: crc_at,
+                expected: payload_crc,
+                actual: crc,
+            });
+        }
+        // before decoding, so a frame garbled on the line is not taken for
+        // a forged one
+        if crc_check == CrcCheck::Strict && !crc_errors.is_empty() {
+            return Err(crc_errors.remove(0));
+        }
+        let payload = match cipher {
+            Some(cipher) => {
+                let sender = var_header.sender.ok_or(IrsError::MissingField {
+                    msg_type: header.msg_type,
+                    field: "sender",
+                })?;
+                Payload::from_bytes_sealed(
+                    payload_bytes,
+                    registry,
+                    cipher,
+                    sender,
+                    &header,
+                    &bytes[Header::SIZE..payload_at],
+                )
+            }
+            None => Payload::from_bytes_in(payload_bytes, registry),
+        }
+        .map_err(|e| e.at(payload_at))?;
+        Ok(Self {
             header,
             var_header,
             payload,
-            body: Vec::new(),
-        }
+            crc_errors,
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
-   #[test] 
-   fn test_msg_ser() {
-       
-   }
-}
\ No newline at end of file
+    use super::*;
+
+    include!("../testdata/frames.rs");
+
+    #[test]
+    fn test_crc_algorithm() {
+        // CRC-16/ARC check value
+        assert_eq!(header::CRC16.checksum(b"123456789"), 0xbb3d);
+    }
+
+    #[test]
+    fn test_reference_frames_crc() {
+        let msg = Msg::from_bytes(&ENCODED_470).unwrap();
+        assert!(msg.crc_errors().is_empty());
+        assert_eq!(msg.var_header().client_id, Some(1));
+        assert_eq!(
+            msg.sender(),
+            Some(DeviceCode::PcConnectedToMainBoardUartInterface)
+        );
+        assert_eq!(
+            msg.receiver(),
+            Some(DeviceCode::MowerMainBoardApplicationSw)
+        );
+        assert_eq!(msg.header.crc, 0x7884);
+        assert_eq!(msg.payload.crc, 0x0c3d);
+        assert_eq!(msg.get_msg_id(), 5);
+
+        let msg = Msg::from_bytes(&ENCODED_471).unwrap();
+        assert_eq!(msg.header.crc, 0x2a05);
+        assert_eq!(msg.payload.crc, 0xcabf);
+        let data: &params::data::Param471 = msg.get_param(0).unwrap().get().unwrap();
+        assert_eq!(data.default_cutting_height, 30);
+        assert_eq!(data.current_cutting_height, 45);
+    }
+
+    #[test]
+    fn test_crc_strict_and_lenient() {
+        // corrupt the var header: only the header CRC covers it
+        let mut bytes = ENCODED_470;
+        bytes[11] ^= 0xff;
+        assert!(matches!(
+            Msg::from_bytes(&bytes),
+            Err(IrsError::CrcMismatch {
+                offset: 5,
+                expected: _,
+                actual: 0x7884,
+            })
+        ));
+        // corrupt the msg_id: only the payload CRC covers it
+        let mut bytes = ENCODED_470;
+        bytes[13] = 0x07;
+        assert!(matches!(
+            Msg::from_bytes(&bytes),
+            Err(IrsError::CrcMismatch {
+                offset: 20,
+                expected: _,
+                actual: 0x0c3d,
+            })
+        ));
+        let msg = Msg::from_bytes_with(&bytes, CrcCheck::Lenient).unwrap();
+        assert_eq!(msg.get_msg_id(), 7);
+        assert_eq!(msg.crc_errors().len(), 1);
+        assert!(matches!(
+            msg.crc_errors()[0],
+            IrsError::CrcMismatch { offset: 20, .. }
+        ));
+    }
+
+    #[test]
+    fn test_to_bytes_fills_crc() {
+        let mut msg = Msg::new();
+        msg.set_msg_id(9);
+        msg.add_param(Param::of(params::data::Param470));
+        let bytes = msg.to_bytes().unwrap();
+        let crc_at = bytes.len() - 2;
+        assert_eq!(
+            u16::from_le_bytes([bytes[crc_at], bytes[crc_at + 1]]),
+            header::CRC16.checksum(&bytes[13..crc_at])
+        );
+        assert_ne!(u16::from_le_bytes([bytes[5], bytes[6]]), 0);
+        let decoded = Msg::from_bytes(&bytes).unwrap();
+        assert!(decoded.crc_errors().is_empty());
+        assert_eq!(decoded.get_msg_id(), 9);
+    }
+
+    #[test]
+    fn test_msg_truncated() {
+        let mut msg = Msg::new();
+        msg.add_param(Param::of(params::data::Param470));
+        let bytes = msg.to_bytes().unwrap();
+        for len in 0..bytes.len() {
+            let res = Msg::from_bytes(&bytes[..len]);
+            assert!(matches!(res, Err(IrsError::Truncated { .. })), "len {len}");
+        }
+    }
+
+    #[test]
+    fn test_msg_trailing_junk() {
+        let mut bytes = ENCODED_470.to_vec();
+        bytes.push(0x00);
+        assert!(matches!(
+            Msg::from_bytes(&bytes),
+            Err(IrsError::LengthMismatch {
+                offset: 22,
+                expected: 22,
+                actual: 23
+            })
+        ));
+    }
+
+    #[test]
+    fn test_too_long_to_encode() {
+        let mut msg = Msg::new();
+        for _ in 0..2 {
+            msg.add_param(Param::new(
+                0x1234,
+                params::ParamPayload::raw(0x1234, vec![0; 0x8000]),
+            ));
+        }
+        assert!(matches!(
+            msg.to_bytes(),
+            Err(IrsError::TooLong {
+                field: "clear parameters",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_reference_encode() {
+        let mut msg = Msg::new();
+        msg.set_msg_id(5);
+        msg.add_param(Param::of(params::data::Param470));
+        assert_eq!(msg.to_bytes().unwrap(), ENCODED_470);
+    }
+
+    #[test]
+    fn test_round_trip_all_types() {
+        let types = [
+            MsgType::Connect,
+            MsgType::ConnectAck,
+            MsgType::Data,
+            MsgType::DisConnect,
+            MsgType::ConnectExtended,
+            MsgType::ConnectExtendedAck,
+            MsgType::DisConnectExtended,
+        ];
+        for msg_type in types {
+            let mut msg = Msg::new();
+            msg.set_message_type(msg_type);
+            msg.set_client_id(0x11223344);
+            msg.set_msg_id(42);
+            msg.add_param(Param::of(params::data::Param470));
+            let bytes = msg.to_bytes().unwrap();
+            let var_header_size = VarHeader::default_size(msg_type).unwrap() as usize;
+            let payload_length = u16::from_le_bytes([bytes[3], bytes[4]]) as usize;
+            assert_eq!(
+                payload_length,
+                msg.payload.to_bytes().unwrap().len(),
+                "{msg_type:?}"
+            );
+            assert_eq!(
+                bytes.len(),
+                Header::SIZE + var_header_size + payload_length,
+                "{msg_type:?}"
+            );
+
+            let decoded = Msg::from_bytes(&bytes).unwrap();
+            assert_eq!(decoded.get_message_type(), msg_type);
+            assert_eq!(decoded.get_msg_id(), 42);
+            assert_eq!(decoded.payload().get_params().len(), 1);
+            assert_eq!(decoded.header().payload_length as usize, payload_length);
+            let vh = decoded.var_header();
+            match msg_type {
+                MsgType::ConnectAck => {
+                    assert_eq!(vh.connect_return_code, msg.var_header.connect_return_code)
+                }
+                _ => assert_eq!(vh.client_id, Some(0x11223344), "{msg_type:?}"),
+            }
+            assert_eq!(decoded.to_bytes().unwrap(), bytes, "{msg_type:?}");
+        }
+    }
+
+    #[test]
+    fn test_unmodelled_params_forwarded_unchanged() {
+        // what newer firmware might send: a parameter we do not know and a
+        // 471 with a fifth data byte
+        let mut msg = Msg::new();
+        msg.set_msg_id(9);
+        msg.add_param(Param::new(
+            0x1234,
+            params::ParamPayload::raw(0x1234, [1, 2, 3]),
+        ));
+        msg.add_param(Param::new(
+            471,
+            params::ParamPayload::raw(471, [0, 30, 45, 0, 7]),
+        ));
+        msg.add_param(Param::of(params::data::Param470));
+        let bytes = msg.to_bytes().unwrap();
+
+        let decoded = Msg::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            decoded.get_param(0).unwrap().data.as_raw(),
+            Some(&[1, 2, 3][..])
+        );
+        assert_eq!(
+            decoded.get_param(1).unwrap().data.as_raw(),
+            Some(&[0, 30, 45, 0, 7][..])
+        );
+        assert!(
+            decoded
+                .get_param(2)
+                .unwrap()
+                .get::<params::data::Param470>()
+                .is_some()
+        );
+        assert_eq!(decoded.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_undefined_type_rejected() {
+        let mut bytes = ENCODED_470;
+        bytes[2] = MsgType::Undefined as u8;
+        assert!(matches!(
+            Msg::from_bytes(&bytes),
+            Err(IrsError::UnknownMsgType {
+                offset: 2,
+                value: 0
+            })
+        ));
+    }
+}
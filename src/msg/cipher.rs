@@ -0,0 +1,430 @@
+//! Authenticated encryption of the payload past its unencrypted prefix.
+//!
+//! The UeLen field of a payload counts the parameter bytes sent in clear;
+//! everything after them up to the CRC is the sealed section:
+//!
+//! ```text
+//!    |-------|--------------|----------|----------------------|----------|-----|
+//!    | MsgId | UeLen        | Clear    | Sequence | Encrypted | Tag      | CRC |
+//!    |       |              | params   | 64 bits  | params    | 128 bits |     |
+//!    |-------+--------------+----------+----------+-----------+----------+-----|
+//! ```
+//!
+//! The sender numbers the frames it seals and sends the number in clear, so
+//! a lost or reordered frame does not keep the following ones from opening.
+//! The AEAD nonce is made of that number and the sender's device code; the
+//! tag covers the encrypted parameters and, as associated data, the header
+//! up to its CRC, the var header, MsgId, UeLen and the clear parameters. A
+//! frame altered, retyped or readdressed on the way fails to open, and
+//! one whose number was received before is refused as a replay. The CRC
+//! covers the bytes as sent, so a frame can be checked, routed and forwarded
+//! without the key.
+
+use std::{
+    fmt,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+#[cfg(feature = "aes")]
+use aes_gcm::{AeadInPlace, KeyInit, Nonce};
+
+use super::header::DeviceCode;
+use crate::error::IrsError;
+
+/// Size of the sequence number leading the sealed section.
+pub const SEQ_SIZE: usize = 8;
+/// Size of the authentication tag ending the sealed section.
+pub const TAG_SIZE: usize = 16;
+
+/// How far below the highest sequence number received a frame may still
+/// arrive, in case frames overtake each other.
+const REPLAY_WINDOW: u64 = 64;
+
+/// An AEAD with 96 bit nonces and [`TAG_SIZE`] tags.
+///
+/// A nonce must never repeat under the same key; [`SessionCipher`] builds
+/// them from the sender and a sequence number.
+pub trait PayloadCipher: Send + Sync {
+    /// Encrypt `data` in place and append the tag over it and `aad`.
+    fn seal(&self, nonce: &[u8; 12], aad: &[u8], data: &mut Vec<u8>) -> Result<(), IrsError>;
+    /// Check and strip the tag ending `data`, then decrypt it in place.
+    /// Fails with [`IrsError::Unauthenticated`] if `data` or `aad` changed.
+    fn open(&self, nonce: &[u8; 12], aad: &[u8], data: &mut Vec<u8>) -> Result<(), IrsError>;
+}
+
+/// AES-128-GCM, in software.
+#[cfg(feature = "aes")]
+#[derive(Clone)]
+pub struct Aes128Gcm {
+    cipher: aes_gcm::Aes128Gcm,
+}
+
+#[cfg(feature = "aes")]
+impl Aes128Gcm {
+    pub fn new(key: [u8; 16]) -> Self {
+        Aes128Gcm {
+            cipher: aes_gcm::Aes128Gcm::new(&key.into()),
+        }
+    }
+}
+
+#[cfg(feature = "aes")]
+impl fmt::Debug for Aes128Gcm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Aes128Gcm").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "aes")]
+impl PayloadCipher for Aes128Gcm {
+    fn seal(&self, nonce: &[u8; 12], aad: &[u8], data: &mut Vec<u8>) -> Result<(), IrsError> {
+        self.cipher
+            .encrypt_in_place(Nonce::from_slice(nonce), aad, data)
+            .map_err(|_| IrsError::TooLong {
+                field: "encrypted parameters",
+                len: data.len(),
+            })
+    }
+    fn open(&self, nonce: &[u8; 12], aad: &[u8], data: &mut Vec<u8>) -> Result<(), IrsError> {
+        self.cipher
+            .decrypt_in_place(Nonce::from_slice(nonce), aad, data)
+            .map_err(|_| IrsError::Unauthenticated)
+    }
+}
+
+/// One end of an encrypted session: the ciphers of both directions, the
+/// next sequence number to send and the ones received lately. Shared by
+/// whatever encodes and decodes the frames of the session.
+///
+/// Sequence numbers start at zero, so the keys must be fresh for every
+/// session, e.g. those from the key exchange. With one key for both
+/// directions the two ends must also use different device codes.
+pub struct SessionCipher {
+    sealing: Arc<dyn PayloadCipher>,
+    opening: Arc<dyn PayloadCipher>,
+    next_seq: AtomicU64,
+    received: Mutex<ReplayWindow>,
+}
+
+impl SessionCipher {
+    /// Seal and open with the same `cipher`.
+    pub fn new(cipher: impl PayloadCipher + 'static) -> Self {
+        let cipher = Arc::new(cipher);
+        Self::with_ciphers(cipher.clone(), cipher)
+    }
+
+    /// Seal the frames this end sends with `sealing` and open the ones it
+    /// receives with `opening`, the peer's `sealing`.
+    pub fn directional(
+        sealing: impl PayloadCipher + 'static,
+        opening: impl PayloadCipher + 'static,
+    ) -> Self {
+        Self::with_ciphers(Arc::new(sealing), Arc::new(opening))
+    }
+
+    fn with_ciphers(sealing: Arc<dyn PayloadCipher>, opening: Arc<dyn PayloadCipher>) -> Self {
+        SessionCipher {
+            sealing,
+            opening,
+            next_seq: AtomicU64::new(0),
+            received: Mutex::new(ReplayWindow::default()),
+        }
+    }
+
+    /// Encrypt `data` as the next frame from `sender`, authenticating `aad`
+    /// with it. Returns the sealed section.
+    pub(crate) fn seal(
+        &self,
+        sender: DeviceCode,
+        aad: &[u8],
+        mut data: Vec<u8>,
+    ) -> Result<Vec<u8>, IrsError> {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        self.sealing.seal(&nonce(sender, seq), aad, &mut data)?;
+        let mut sealed = Vec::with_capacity(SEQ_SIZE + data.len());
+        sealed.extend_from_slice(&seq.to_le_bytes());
+        sealed.extend_from_slice(&data);
+        Ok(sealed)
+    }
+
+    /// Open a sealed section from `sender`, refusing sequence numbers
+    /// received before or too old to tell.
+    pub(crate) fn open(
+        &self,
+        sender: DeviceCode,
+        aad: &[u8],
+        sealed: &[u8],
+    ) -> Result<Vec<u8>, IrsError> {
+        if sealed.len() < SEQ_SIZE + TAG_SIZE {
+            return Err(IrsError::Unauthenticated);
+        }
+        let mut seq = [0; SEQ_SIZE];
+        seq.copy_from_slice(&sealed[..SEQ_SIZE]);
+        let seq = u64::from_le_bytes(seq);
+        let mut received = self.received.lock().unwrap();
+        if !received.is_fresh(seq) {
+            return Err(IrsError::Replayed(seq));
+        }
+        let mut data = sealed[SEQ_SIZE..].to_vec();
+        self.opening.open(&nonce(sender, seq), aad, &mut data)?;
+        received.mark(seq);
+        Ok(data)
+    }
+}
+
+impl fmt::Debug for SessionCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionCipher")
+            .field("next_seq", &self.next_seq)
+            .finish_non_exhaustive()
+    }
+}
+
+/// AEAD nonce of frame `seq` from `sender`.
+fn nonce(sender: DeviceCode, seq: u64) -> [u8; 12] {
+    let mut nonce = [0; 12];
+    nonce[0] = sender.into();
+    nonce[4..].copy_from_slice(&seq.to_be_bytes());
+    nonce
+}
+
+/// Sequence numbers received lately.
+#[derive(Debug, Default)]
+struct ReplayWindow {
+    highest: Option<u64>,
+    /// Bit `n` is set once `highest - n` was received.
+    seen: u64,
+}
+
+impl ReplayWindow {
+    fn is_fresh(&self, seq: u64) -> bool {
+        match self.highest {
+            Some(highest) if seq <= highest => {
+                let age = highest - seq;
+                age < REPLAY_WINDOW && self.seen & (1 << age) == 0
+            }
+            _ => true,
+        }
+    }
+
+    fn mark(&mut self, seq: u64) {
+        match self.highest {
+            Some(highest) if seq <= highest => self.seen |= 1 << (highest - seq),
+            _ => {
+                let shift = self.highest.map_or(REPLAY_WINDOW, |highest| seq - highest);
+                self.seen = if shift < REPLAY_WINDOW {
+                    self.seen << shift
+                } else {
+                    0
+                };
+                self.seen |= 1;
+                self.highest = Some(seq);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "aes"))]
+mod tests {
+    use super::*;
+    use crate::msg::{
+        Msg,
+        header::{Header, MsgType, VarHeader},
+        params::{Param, ParamRegistry, data},
+        payload::Payload,
+    };
+
+    #[test]
+    fn test_aes128_gcm() {
+        // McGrew & Viega, The Galois/Counter Mode of Operation, test cases 1 and 2
+        let cipher = Aes128Gcm::new([0; 16]);
+        let mut data = Vec::new();
+        cipher.seal(&[0; 12], &[], &mut data).unwrap();
+        assert_eq!(
+            data,
+            [
+                0x58, 0xe2, 0xfc, 0xce, 0xfa, 0x7e, 0x30, 0x61, 0x36, 0x7f, 0x1d, 0x57, 0xa4, 0xe7,
+                0x45, 0x5a,
+            ]
+        );
+        let mut data = vec![0; 16];
+        cipher.seal(&[0; 12], &[], &mut data).unwrap();
+        assert_eq!(
+            data,
+            [
+                0x03, 0x88, 0xda, 0xce, 0x60, 0xb6, 0xa3, 0x92, 0xf3, 0x28, 0xc2, 0xb9, 0x71, 0xb2,
+                0xfe, 0x78, 0xab, 0x6e, 0x47, 0xd4, 0x2c, 0xec, 0x13, 0xbd, 0xf5, 0x3a, 0x67, 0xb2,
+                0x12, 0x57, 0xbd, 0xdf,
+            ]
+        );
+        cipher.open(&[0; 12], &[], &mut data).unwrap();
+        assert_eq!(data, [0; 16]);
+    }
+
+    fn msg() -> Msg {
+        let mut msg = Msg::new();
+        msg.add_param(Param::of(data::Param471 {
+            return_code: 0,
+            default_cutting_height: 30,
+            current_cutting_height: 40,
+            information: 0,
+        }));
+        msg.add_param(Param::of(data::Param470));
+        msg.payload_mut().encrypt_from = 1;
+        msg
+    }
+
+    fn session() -> SessionCipher {
+        SessionCipher::new(Aes128Gcm::new([1; 16]))
+    }
+
+    /// Decode `bytes` with `rx`, parameters from the global registry.
+    fn open(bytes: &[u8], rx: &SessionCipher) -> Result<Msg, IrsError> {
+        Msg::from_bytes_sealed(bytes, ParamRegistry::global(), rx)
+    }
+
+    /// Start of the payload in a Data frame.
+    fn payload_at() -> usize {
+        Header::SIZE + VarHeader::default_size(MsgType::Data).unwrap() as usize
+    }
+
+    /// Flip a bit at `at` and fix up the payload CRC, as an attacker would.
+    fn tamper(bytes: &[u8], at: usize) -> Vec<u8> {
+        let mut bytes = bytes.to_vec();
+        bytes[at] ^= 0x01;
+        let payload_at = payload_at();
+        let crc_at = bytes.len() - 2;
+        let crc = Payload::crc_over(&bytes[payload_at..]);
+        bytes[crc_at..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_sealed_payload() {
+        let (tx, rx) = (session(), session());
+        let bytes = msg().to_bytes_sealed(&tx).unwrap();
+
+        // decodes, CRC and all, without the key, and forwards unchanged
+        let received = Msg::from_bytes(&bytes).unwrap();
+        let payload = received.payload();
+        assert_eq!(payload.unencrypted_length, 8);
+        assert_eq!(payload.get_params().len(), 1);
+        assert_eq!(payload.encrypted.len(), SEQ_SIZE + 4 + TAG_SIZE);
+        assert_eq!(payload.encrypted[..SEQ_SIZE], [0; SEQ_SIZE]);
+        assert_eq!(received.to_bytes().unwrap(), bytes);
+
+        let received = open(&bytes, &rx).unwrap();
+        assert_eq!(received.payload().get_params().len(), 2);
+        assert_eq!(received.payload().encrypt_from, 1);
+        assert!(
+            received
+                .get_param(1)
+                .unwrap()
+                .get::<data::Param470>()
+                .is_some()
+        );
+        assert!(matches!(open(&bytes, &rx), Err(IrsError::Replayed(0))));
+
+        // the next frame gets the next sequence number
+        let bytes = msg().to_bytes_sealed(&tx).unwrap();
+        assert_eq!(open(&bytes, &rx).unwrap().payload().get_params().len(), 2);
+    }
+
+    #[test]
+    fn test_tampering_detected() {
+        let (tx, rx) = (session(), session());
+        let bytes = msg().to_bytes_sealed(&tx).unwrap();
+        let payload_at = payload_at();
+        // tag, encrypted bytes, sequence number, clear parameters, msg_id
+        for at in [
+            bytes.len() - 3,
+            bytes.len() - 2 - TAG_SIZE - 1,
+            payload_at + 11 + 2,
+            payload_at + 9,
+            payload_at,
+        ] {
+            assert!(
+                matches!(
+                    open(&tamper(&bytes, at), &rx),
+                    Err(IrsError::Unauthenticated)
+                ),
+                "{at}"
+            );
+        }
+        // failed attempts do not use up the sequence number
+        open(&bytes, &rx).unwrap();
+
+        // nor does stripping the sealed section pass
+        let mut clear = msg();
+        clear.payload_mut().encrypt_from = 2;
+        let bytes = clear.to_bytes().unwrap();
+        assert!(matches!(open(&bytes, &rx), Err(IrsError::Unauthenticated)));
+    }
+
+    #[test]
+    fn test_lost_and_reordered_frames() {
+        let (tx, rx) = (session(), session());
+        let frames: Vec<_> = (0..4)
+            .map(|_| msg().to_bytes_sealed(&tx).unwrap())
+            .collect();
+        // frame 0 is lost, 3 overtakes 2
+        open(&frames[1], &rx).unwrap();
+        open(&frames[3], &rx).unwrap();
+        open(&frames[2], &rx).unwrap();
+        assert!(matches!(open(&frames[2], &rx), Err(IrsError::Replayed(2))));
+
+        let mut window = ReplayWindow::default();
+        window.mark(100);
+        assert!(window.is_fresh(100 - REPLAY_WINDOW + 1));
+        assert!(!window.is_fresh(100 - REPLAY_WINDOW));
+        window.mark(100 + REPLAY_WINDOW);
+        assert!(window.is_fresh(101));
+        assert!(!window.is_fresh(100));
+    }
+
+    /// Set byte `at` of the header or var header to `value` and fix up the
+    /// header CRC.
+    fn forge_header(bytes: &[u8], at: usize, value: u8) -> Vec<u8> {
+        let mut forged = bytes.to_vec();
+        forged[at] = value;
+        let crc = Header::crc_over(
+            &forged[..Header::CRC_OFFSET],
+            &forged[Header::SIZE..payload_at()],
+        );
+        forged[Header::CRC_OFFSET..Header::SIZE].copy_from_slice(&crc.to_le_bytes());
+        forged
+    }
+
+    #[test]
+    fn test_nonce_depends_on_sender() {
+        let (tx, rx) = (session(), session());
+        let bytes = msg()
+            .with_sender(DeviceCode::MobileApp)
+            .to_bytes_sealed(&tx)
+            .unwrap();
+        let forged = forge_header(&bytes, Header::SIZE + 4, DeviceCode::Backend.into());
+        assert!(matches!(open(&forged, &rx), Err(IrsError::Unauthenticated)));
+        open(&bytes, &rx).unwrap();
+    }
+
+    #[test]
+    fn test_header_authenticated() {
+        let (tx, rx) = (session(), session());
+        let bytes = msg()
+            .with_receiver(DeviceCode::MowerMainBoardApplicationSw)
+            .to_bytes_sealed(&tx)
+            .unwrap();
+        // same var header layout, so both still decode
+        for forged in [
+            forge_header(&bytes, 2, MsgType::DisConnectExtended as u8),
+            forge_header(&bytes, Header::SIZE + 5, DeviceCode::Backend.into()),
+        ] {
+            Msg::from_bytes(&forged).unwrap();
+            assert!(matches!(open(&forged, &rx), Err(IrsError::Unauthenticated)));
+        }
+        open(&bytes, &rx).unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+use tracing::debug;
+
+use super::{Msg, cipher::SessionCipher, frame::FrameDecoder, params::ParamRegistry};
+use crate::error::IrsError;
+
+/// [`tokio_util::codec`] adapter for IRS frames.
+///
+/// Wrapping a byte stream as `Framed::new(stream, IrsCodec::new())` gives a
+/// `Stream` of `Result<Msg, IrsError>` and a `Sink<Msg>`. Decoding goes
+/// through a [`FrameDecoder`], so line noise and bad frames are skipped the
+/// same way as on the synchronous transports rather than ending the stream.
+#[derive(Debug, Default)]
+pub struct IrsCodec {
+    decoder: FrameDecoder,
+    cipher: Option<Arc<SessionCipher>>,
+}
+
+impl IrsCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Decode parameters with `registry` instead of the global one.
+    pub fn with_registry(mut self, registry: ParamRegistry) -> Self {
+        self.decoder = self.decoder.with_registry(registry);
+        self
+    }
+    /// Seal the payloads encoded and open those decoded with `cipher` from
+    /// here on, or stop doing so with `None`.
+    pub fn set_cipher(&mut self, cipher: Option<Arc<SessionCipher>>) {
+        self.decoder.set_cipher(cipher.clone());
+        self.cipher = cipher;
+    }
+    /// Decoder state, e.g. its dropped byte and bad frame counters.
+    pub fn decoder(&self) -> &FrameDecoder {
+        &self.decoder
+    }
+}
+
+impl Decoder for IrsCodec {
+    type Item = Msg;
+    type Error = IrsError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Msg>, IrsError> {
+        if !src.is_empty() {
+            self.decoder.push(src);
+            src.clear();
+        }
+        Ok(self.decoder.next_msg())
+    }
+
+    /// Like [`IrsCodec::decode`], but the stream ended: the bytes of a frame
+    /// cut short are dropped, failing with [`IrsError::Closed`].
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Msg>, IrsError> {
+        if let Some(msg) = self.decode(src)? {
+            return Ok(Some(msg));
+        }
+        if self.decoder.buffered() > 0 {
+            debug!("stream ended inside a frame");
+            self.decoder.clear();
+            return Err(IrsError::Closed);
+        }
+        Ok(None)
+    }
+}
+
+impl Encoder<Msg> for IrsCodec {
+    type Error = IrsError;
+
+    fn encode(&mut self, msg: Msg, dst: &mut BytesMut) -> Result<(), IrsError> {
+        let bytes = match &self.cipher {
+            Some(cipher) => msg.to_bytes_sealed(cipher)?,
+            None => msg.to_bytes()?,
+        };
+        dst.extend_from_slice(&bytes);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::params::{Param, data::Param470};
+
+    fn request(msg_id: u8) -> Msg {
+        let mut msg = Msg::new();
+        msg.set_msg_id(msg_id);
+        msg.add_param(Param::of(Param470));
+        msg
+    }
+
+    #[test]
+    fn test_codec_round_trip() {
+        let mut codec = IrsCodec::new();
+        let mut wire = BytesMut::from(&[0xff, 0x01, 0x00][..]);
+        codec.encode(request(1), &mut wire).unwrap();
+        codec.encode(request(2), &mut wire).unwrap();
+
+        // feed it a few bytes at a time, as a socket would
+        let mut msg_ids = Vec::new();
+        let mut src = BytesMut::new();
+        for chunk in wire.chunks(5) {
+            src.extend_from_slice(chunk);
+            while let Some(msg) = codec.decode(&mut src).unwrap() {
+                msg_ids.push(msg.get_msg_id());
+            }
+        }
+        assert_eq!(msg_ids, [1, 2]);
+        assert_eq!(codec.decoder().dropped_bytes(), 3);
+        assert!(codec.decode_eof(&mut src).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_eof_inside_frame() {
+        let mut codec = IrsCodec::new();
+        let mut wire = BytesMut::new();
+        codec.encode(request(1), &mut wire).unwrap();
+        let mut src = BytesMut::from(&wire[..wire.len() / 2]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        assert!(matches!(codec.decode_eof(&mut src), Err(IrsError::Closed)));
+        assert_eq!(codec.decoder().buffered(), 0);
+        assert_eq!(codec.decoder().dropped_bytes(), (wire.len() / 2) as u64);
+        assert!(codec.decode_eof(&mut src).unwrap().is_none());
+    }
+}
@@ -0,0 +1,238 @@
+use std::sync::Arc;
+
+use tracing::debug;
+
+use super::{
+    CrcCheck, Msg,
+    cipher::SessionCipher,
+    header::{Header, VarHeader},
+    params::ParamRegistry,
+};
+use crate::error::IrsError;
+
+/// Start of every frame: SOH followed by STX.
+const START: [u8; 2] = [0x01, 0x02];
+
+/// Incremental frame decoder for byte streams such as a serial link.
+///
+/// Bytes are fed with [`FrameDecoder::push`] in whatever chunks they arrive and
+/// complete frames are taken out with [`FrameDecoder::next_msg`]. Anything that
+/// is not part of a valid frame is discarded: the decoder scans for the
+/// `0x01 0x02` start sequence, checks the header CRC before trusting
+/// `payload_length`, and resumes scanning right after the SOH of a candidate
+/// with a bad header, or after the whole frame once its header checked out.
+///
+/// Parameters are decoded with the global [`ParamRegistry`] unless another
+/// one is set with [`FrameDecoder::with_registry`]. Once a cipher is set with
+/// [`FrameDecoder::set_cipher`], frames must carry a sealed section that opens
+/// with it; others are rejected.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+    registry: Option<ParamRegistry>,
+    cipher: Option<Arc<SessionCipher>>,
+    dropped_bytes: u64,
+    bad_frames: u64,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_registry(mut self, registry: ParamRegistry) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+    pub fn registry(&self) -> &ParamRegistry {
+        self.registry.as_ref().unwrap_or(ParamRegistry::global())
+    }
+    /// Open the payloads of the frames from here on with `cipher`, or stop
+    /// doing so with `None`.
+    pub fn set_cipher(&mut self, cipher: Option<Arc<SessionCipher>>) {
+        self.cipher = cipher;
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Push `bytes` and return every frame completed by them.
+    pub fn decode(&mut self, bytes: &[u8]) -> Vec<Msg> {
+        self.push(bytes);
+        let mut msgs = Vec::new();
+        while let Some(msg) = self.next_msg() {
+            msgs.push(msg);
+        }
+        msgs
+    }
+
+    /// Next complete frame in the buffer, or `None` if more bytes are needed.
+    pub fn next_msg(&mut self) -> Option<Msg> {
+        loop {
+            if !self.sync() {
+                return None;
+            }
+            let header = match Header::try_from(&self.buf[..]) {
+                Ok(header) => header,
+                Err(IrsError::Truncated { .. }) => return None,
+                Err(e) => {
+                    debug!("bad frame header: {e}");
+                    self.reject(1);
+                    continue;
+                }
+            };
+            let Some(var_header_size) = VarHeader::default_size(header.msg_type) else {
+                self.reject(1);
+                continue;
+            };
+            let payload_at = Header::SIZE + var_header_size as usize;
+            if self.buf.len() < payload_at {
+                return None;
+            }
+            let crc = Header::crc_over(
+                &self.buf[..Header::CRC_OFFSET],
+                &self.buf[Header::SIZE..payload_at],
+            );
+            if crc != header.crc {
+                debug!("bad header crc: {crc:#06x} != {:#06x}", header.crc);
+                self.reject(1);
+                continue;
+            }
+            // the header CRC vouches for payload_length from here on
+            let frame_len = payload_at + header.payload_length as usize;
+            if self.buf.len() < frame_len {
+                return None;
+            }
+            let frame = &self.buf[..frame_len];
+            let decoded = match &self.cipher {
+                Some(cipher) => Msg::from_bytes_sealed(frame, self.registry(), cipher),
+                None => Msg::from_bytes_in(frame, CrcCheck::Strict, self.registry()),
+            };
+            match decoded {
+                Ok(msg) => {
+                    self.buf.drain(..frame_len);
+                    return Some(msg);
+                }
+                Err(e) => {
+                    debug!("bad frame: {e}");
+                    self.reject(frame_len);
+                }
+            }
+        }
+    }
+
+    /// Bytes discarded so far, both line noise and the bytes of bad frames.
+    pub fn dropped_bytes(&self) -> u64 {
+        self.dropped_bytes
+    }
+
+    /// Candidate frames rejected so far.
+    pub fn bad_frames(&self) -> u64 {
+        self.bad_frames
+    }
+
+    /// Bytes held back waiting for the rest of a frame.
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Drop everything buffered, e.g. after the underlying link was reopened.
+    pub fn clear(&mut self) {
+        self.dropped_bytes += self.buf.len() as u64;
+        self.buf.clear();
+    }
+
+    /// Discard bytes up to the next start sequence; true if one is at the front.
+    fn sync(&mut self) -> bool {
+        let start = self.buf.windows(2).position(|w| w == START);
+        let skip = match start {
+            Some(at) => at,
+            // keep a trailing SOH, its STX may be in the next chunk
+            None if self.buf.last() == Some(&START[0]) => self.buf.len() - 1,
+            None => self.buf.len(),
+        };
+        if skip > 0 {
+            self.dropped_bytes += skip as u64;
+            self.buf.drain(..skip);
+        }
+        start.is_some()
+    }
+
+    fn reject(&mut self, len: usize) {
+        self.bad_frames += 1;
+        self.dropped_bytes += len as u64;
+        self.buf.drain(..len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    include!("../../testdata/frames.rs");
+
+    #[test]
+    fn test_byte_by_byte() {
+        let mut decoder = FrameDecoder::new();
+        let mut msgs = Vec::new();
+        for b in ENCODED_470.iter().chain(ENCODED_471.iter()) {
+            msgs.extend(decoder.decode(&[*b]));
+        }
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0].get_msg_id(), 5);
+        assert_eq!(msgs[1].get_msg_id(), 6);
+        assert_eq!(decoder.dropped_bytes(), 0);
+        assert_eq!(decoder.bad_frames(), 0);
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn test_garbage_between_frames() {
+        let mut decoder = FrameDecoder::new();
+        let mut stream = vec![0xff, 0x00, 0x01, 0x01];
+        stream.extend_from_slice(&ENCODED_470);
+        stream.extend_from_slice(&[0x02, 0x01, 0x03]);
+        stream.extend_from_slice(&ENCODED_471);
+        stream.push(0x01);
+        let msgs = decoder.decode(&stream);
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(decoder.dropped_bytes(), 7);
+        assert_eq!(decoder.bad_frames(), 0);
+        // the trailing SOH is kept until the next chunk shows what follows
+        assert_eq!(decoder.buffered(), 1);
+    }
+
+    #[test]
+    fn test_resync_after_corrupt_frames() {
+        let mut decoder = FrameDecoder::new();
+        // payload CRC broken: header is trusted, the whole frame is skipped
+        let mut bad_payload = ENCODED_470;
+        bad_payload[16] ^= 0x10;
+        // header CRC broken: only the SOH is skipped, then the rest is noise
+        let mut bad_header = ENCODED_471;
+        bad_header[3] = 0x20;
+        let mut stream = bad_payload.to_vec();
+        stream.extend_from_slice(&bad_header);
+        stream.extend_from_slice(&ENCODED_471);
+        let msgs = decoder.decode(&stream);
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].get_msg_id(), 6);
+        assert_eq!(decoder.bad_frames(), 2);
+        assert_eq!(
+            decoder.dropped_bytes(),
+            (bad_payload.len() + bad_header.len()) as u64
+        );
+    }
+
+    #[test]
+    fn test_false_start_inside_frame() {
+        let mut decoder = FrameDecoder::new();
+        // a lone SOH STX pair followed by an unknown message type
+        let mut stream = vec![0x01, 0x02, 0x63];
+        stream.extend_from_slice(&ENCODED_470);
+        let msgs = decoder.decode(&stream);
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(decoder.bad_frames(), 1);
+        assert_eq!(decoder.dropped_bytes(), 3);
+    }
+}
@@ -1,5 +1,10 @@
+use std::fmt;
+
 use crc::{CRC_16_ARC, Crc};
-use tracing::{debug, info};
+
+use crate::error::{IrsError, ensure_len};
+
+pub(crate) const CRC16: Crc<u16> = Crc::<u16>::new(&CRC_16_ARC);
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum MsgType {
@@ -31,19 +36,69 @@ impl TryFrom<u8> for MsgType {
     }
 }
 
-#[repr(u8)]
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-enum DeviceCode {
-    MobileApp = 0x41,
-    Backend = 0x42,
-    ChargingStationApplicationSw = 0x43,
-    MowerMainBoardApplicationSw = 0x4D,
-    PcConnectedToMowerCsConnector = 0x4E,
-    PcConnectedToMainBoardUartInterface = 0x4F,
-    PcConnectedToCsBoard = 0x50,
+/// Address of a node, carried in the var header's sender and receiver.
+///
+/// Every byte converts: codes not listed here become [`DeviceCode::Unknown`],
+/// so the `TryFrom<u8>` that comes with `From<u8>` never fails.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub enum DeviceCode {
+    MobileApp,
+    Backend,
+    ChargingStationApplicationSw,
+    MowerMainBoardApplicationSw,
+    PcConnectedToMowerCsConnector,
+    PcConnectedToMainBoardUartInterface,
+    PcConnectedToCsBoard,
+    Unknown(u8),
+}
+
+impl From<u8> for DeviceCode {
+    fn from(value: u8) -> Self {
+        match value {
+            0x41 => DeviceCode::MobileApp,
+            0x42 => DeviceCode::Backend,
+            0x43 => DeviceCode::ChargingStationApplicationSw,
+            0x4D => DeviceCode::MowerMainBoardApplicationSw,
+            0x4E => DeviceCode::PcConnectedToMowerCsConnector,
+            0x4F => DeviceCode::PcConnectedToMainBoardUartInterface,
+            0x50 => DeviceCode::PcConnectedToCsBoard,
+            other => DeviceCode::Unknown(other),
+        }
+    }
+}
+
+impl From<DeviceCode> for u8 {
+    fn from(code: DeviceCode) -> Self {
+        match code {
+            DeviceCode::MobileApp => 0x41,
+            DeviceCode::Backend => 0x42,
+            DeviceCode::ChargingStationApplicationSw => 0x43,
+            DeviceCode::MowerMainBoardApplicationSw => 0x4D,
+            DeviceCode::PcConnectedToMowerCsConnector => 0x4E,
+            DeviceCode::PcConnectedToMainBoardUartInterface => 0x4F,
+            DeviceCode::PcConnectedToCsBoard => 0x50,
+            DeviceCode::Unknown(value) => value,
+        }
+    }
+}
+
+impl fmt::Display for DeviceCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DeviceCode::MobileApp => "mobile app",
+            DeviceCode::Backend => "backend",
+            DeviceCode::ChargingStationApplicationSw => "charging station",
+            DeviceCode::MowerMainBoardApplicationSw => "mower main board",
+            DeviceCode::PcConnectedToMowerCsConnector => "PC on mower CS connector",
+            DeviceCode::PcConnectedToMainBoardUartInterface => "PC on main board UART",
+            DeviceCode::PcConnectedToCsBoard => "PC on CS board",
+            DeviceCode::Unknown(value) => return write!(f, "device {value:#04x}"),
+        };
+        f.write_str(name)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Header {
     soh: u8,
     stx: u8,
@@ -51,7 +106,14 @@ pub struct Header {
     pub payload_length: u16,
     pub crc: u16,
 }
+impl Default for Header {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl Header {
+    pub const SIZE: usize = 7;
+
     pub fn new() -> Self {
         Header {
             soh: 0x01,
@@ -62,60 +124,115 @@ impl Header {
         }
     }
 
-    // 在message的to bytes中调用
-    fn calculate_crc(&mut self, buf: &mut Vec<u8>) -> u16 {
-        let crc = Crc::<u16>::new(&CRC_16_ARC);
-        self.crc = crc.checksum(buf);
-        buf[5..6].copy_from_slice(&self.crc.to_le_bytes());
+    /// Offset of the CRC field inside the fixed header.
+    pub const CRC_OFFSET: usize = 5;
+
+    /// Compute and store the header CRC: CRC-16/ARC over SOH, STX, message type
+    /// and payload length, followed by the encoded variable header.
+    pub fn calculate_crc(&mut self, var_header: &[u8]) -> u16 {
+        let bytes: Vec<u8> = self.clone().into();
+        self.crc = Self::crc_over(&bytes[..Self::CRC_OFFSET], var_header);
         self.crc
     }
+
+    /// Header CRC of a received frame, `fixed` being its first five bytes.
+    pub fn crc_over(fixed: &[u8], var_header: &[u8]) -> u16 {
+        let mut digest = CRC16.digest();
+        digest.update(fixed);
+        digest.update(var_header);
+        digest.finalize()
+    }
 }
-impl Into<Vec<u8>> for Header {
-    fn into(self) -> Vec<u8> {
-        let mut bytes = vec![0u8; 7];
-        bytes[0] = self.soh;
-        bytes[1] = self.stx;
-        bytes[2] = self.msg_type as u8;
-        bytes[3..5].copy_from_slice(&self.payload_length.to_le_bytes());
-        bytes[5..7].copy_from_slice(&self.crc.to_le_bytes());
+impl From<Header> for Vec<u8> {
+    fn from(header: Header) -> Self {
+        let mut bytes = vec![0u8; Header::SIZE];
+        bytes[0] = header.soh;
+        bytes[1] = header.stx;
+        bytes[2] = header.msg_type as u8;
+        bytes[3..5].copy_from_slice(&header.payload_length.to_le_bytes());
+        bytes[5..7].copy_from_slice(&header.crc.to_le_bytes());
         bytes
     }
 }
-impl From<&[u8]> for Header {
-    fn from(bytes: &[u8]) -> Self {
+impl TryFrom<&[u8]> for Header {
+    type Error = IrsError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        ensure_len(bytes, Header::SIZE)?;
         let mut header = Header::new();
-        header.soh = bytes[0];
-        header.stx = bytes[1];
-        header.msg_type = MsgType::try_from(bytes[2]).unwrap_or(MsgType::Undefined);
+        if bytes[0] != header.soh {
+            return Err(IrsError::BadSoh {
+                offset: 0,
+                found: bytes[0],
+            });
+        }
+        if bytes[1] != header.stx {
+            return Err(IrsError::BadStx {
+                offset: 1,
+                found: bytes[1],
+            });
+        }
+        header.msg_type = match MsgType::try_from(bytes[2]) {
+            Ok(MsgType::Undefined) | Err(()) => {
+                return Err(IrsError::UnknownMsgType {
+                    offset: 2,
+                    value: bytes[2],
+                });
+            }
+            Ok(msg_type) => msg_type,
+        };
         header.payload_length = u16::from_le_bytes([bytes[3], bytes[4]]);
         header.crc = u16::from_le_bytes([bytes[5], bytes[6]]);
-        header
+        Ok(header)
     }
 }
+
 const DEFAULT_PROTOCOL_ID: u8 = 0x06; // production interface
 const DEFAULT_PROTOCOL_VERSION: u8 = 0x02;
 const DEFAULT_KEEP_ALIVE_LSB: u8 = 0;
 const DEFAULT_KEEP_ALIVE_MSB: u8 = 0;
 const DEFAULT_CLIENT_ID: u32 = 0x01;
-const DEFAULT_SENDER: u8 = DeviceCode::PcConnectedToMainBoardUartInterface as u8;
-const DEFAULT_RECEIVER: u8 = DeviceCode::MowerMainBoardApplicationSw as u8;
+const DEFAULT_SENDER: DeviceCode = DeviceCode::PcConnectedToMainBoardUartInterface;
+const DEFAULT_RECEIVER: DeviceCode = DeviceCode::MowerMainBoardApplicationSw;
 const DEFAULT_CONNECT_RETURN_CODE: u8 = 0x09;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct VarHeader {
     pub protocol_id: Option<u8>,
     pub protocol_version: Option<u8>,
     pub keepalive_lsb: Option<u8>,
     pub keepalive_msb: Option<u8>,
-    pub sender: Option<u8>,
-    pub receiver: Option<u8>,
+    pub sender: Option<DeviceCode>,
+    pub receiver: Option<DeviceCode>,
     pub client_id: Option<u32>,
     pub connect_return_code: Option<u8>,
     pub size: u16,
     pub data: Vec<u8>,
 }
 
+impl Default for VarHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl VarHeader {
+    /// A var header with every field unset, as decoding starts from: fields
+    /// a frame type does not carry stay `None`.
+    fn empty() -> Self {
+        VarHeader {
+            protocol_id: None,
+            protocol_version: None,
+            keepalive_lsb: None,
+            keepalive_msb: None,
+            sender: None,
+            receiver: None,
+            client_id: None,
+            connect_return_code: None,
+            size: 0,
+            data: Vec::new(),
+        }
+    }
     pub fn new() -> Self {
         VarHeader {
             protocol_id: Some(DEFAULT_PROTOCOL_ID),
@@ -146,11 +263,23 @@ impl VarHeader {
         self.keepalive_msb = Some(v);
         self
     }
-    pub fn with_sender(mut self, v: u8) -> Self {
+    /// Keep-alive interval in seconds, split into `keepalive_lsb`/`keepalive_msb`.
+    pub fn with_keepalive(mut self, secs: u16) -> Self {
+        self.set_keepalive(secs);
+        self
+    }
+    /// Keep-alive interval in seconds, 0 meaning disabled.
+    pub fn keepalive(&self) -> Option<u16> {
+        Some(u16::from_le_bytes([
+            self.keepalive_lsb?,
+            self.keepalive_msb?,
+        ]))
+    }
+    pub fn with_sender(mut self, v: DeviceCode) -> Self {
         self.sender = Some(v);
         self
     }
-    pub fn with_receiver(mut self, v: u8) -> Self {
+    pub fn with_receiver(mut self, v: DeviceCode) -> Self {
         self.receiver = Some(v);
         self
     }
@@ -166,133 +295,151 @@ impl VarHeader {
     pub fn set_client_id(&mut self, v: u32) {
         self.client_id = Some(v);
     }
+    pub fn set_keepalive(&mut self, secs: u16) {
+        let [lsb, msb] = secs.to_le_bytes();
+        self.keepalive_lsb = Some(lsb);
+        self.keepalive_msb = Some(msb);
+    }
 
-    pub fn build(mut self, msg_type: MsgType) -> Self {
-        self.size = VarHeader::default_size(msg_type).unwrap();
-        match msg_type {
-            MsgType::ConnectExtended => {
-                self.data.push(self.protocol_id.unwrap());
-                self.data.push(DEFAULT_PROTOCOL_VERSION);
-                self.data.push(DEFAULT_KEEP_ALIVE_LSB);
-                self.data.push(DEFAULT_KEEP_ALIVE_MSB);
-                self.data.extend(self.client_id.unwrap().to_le_bytes());
-                self.data.push(self.sender.unwrap());
-                self.data.push(self.receiver.unwrap());
+    /// Encode the fields a `msg_type` frame carries into `data`, in wire
+    /// order. Fails with [`IrsError::MissingField`] if one of them is unset.
+    pub fn build(mut self, msg_type: MsgType) -> Result<Self, IrsError> {
+        let size = Self::default_size(msg_type).ok_or(IrsError::UnknownMsgType {
+            offset: 0,
+            value: msg_type as u8,
+        })?;
+        let need = |v: Option<u8>, field| v.ok_or(IrsError::MissingField { msg_type, field });
+        let mut data = Vec::with_capacity(size as usize);
+        for field in Self::layout(msg_type) {
+            match field {
+                VarField::ProtocolId => data.push(need(self.protocol_id, "protocol_id")?),
+                VarField::ProtocolVersion => {
+                    data.push(need(self.protocol_version, "protocol_version")?)
+                }
+                VarField::Keepalive => {
+                    data.push(need(self.keepalive_lsb, "keepalive_lsb")?);
+                    data.push(need(self.keepalive_msb, "keepalive_msb")?);
+                }
+                VarField::ClientId => {
+                    let client_id = self.client_id.ok_or(IrsError::MissingField {
+                        msg_type,
+                        field: "client_id",
+                    })?;
+                    data.extend(client_id.to_le_bytes());
+                }
+                VarField::Sender => data.push(need(self.sender.map(u8::from), "sender")?),
+                VarField::Receiver => data.push(need(self.receiver.map(u8::from), "receiver")?),
+                VarField::ConnectReturnCode => {
+                    data.push(need(self.connect_return_code, "connect_return_code")?)
+                }
             }
-            MsgType::Connect => {
-                self.data.push(self.protocol_id.unwrap());
-                self.data.push(DEFAULT_PROTOCOL_VERSION);
-                self.data.push(DEFAULT_KEEP_ALIVE_LSB);
-                self.data.push(DEFAULT_KEEP_ALIVE_MSB);
-                self.data.extend(self.client_id.unwrap().to_le_bytes());
-                self.data.push(self.sender.unwrap());
-            }
-            MsgType::ConnectExtendedAck => {
-                self.data.extend(self.client_id.unwrap().to_le_bytes());
-                self.data.push(self.sender.unwrap());
-                self.data.push(self.receiver.unwrap());
-            }
-            MsgType::Data => {
-                self.data.extend(self.client_id.unwrap().to_le_bytes());
-                self.data.push(self.sender.unwrap());
-                self.data.push(self.receiver.unwrap());
-            }
-            MsgType::DisConnectExtended => {
-                self.data.extend(self.client_id.unwrap().to_le_bytes());
-                self.data.push(self.sender.unwrap());
-                self.data.push(self.receiver.unwrap());
-            }
-            MsgType::DisConnect => {
-                self.data.extend(self.client_id.unwrap().to_le_bytes());
-                self.data.push(self.sender.unwrap());
-                self.data.push(self.receiver.unwrap());
-            }
-            _ => {}
-        };
-        self
+        }
+        debug_assert_eq!(data.len(), size as usize);
+        self.size = size;
+        self.data = data;
+        Ok(self)
     }
     pub fn default_size(msg_type: MsgType) -> Option<u16> {
         match msg_type {
-            MsgType::Connect => Some(9),
-            MsgType::ConnectAck => Some(1),
-            MsgType::Data => Some(6),
-            MsgType::DisConnect => Some(5),
-            MsgType::ConnectExtended => Some(10),
-            MsgType::ConnectExtendedAck => Some(7),
-            MsgType::DisConnectExtended => Some(6),
-            _ => None,
+            MsgType::Undefined => None,
+            _ => Some(Self::layout(msg_type).iter().map(|f| f.size() as u16).sum()),
+        }
+    }
+    /// The fields a `msg_type` frame carries, in wire order.
+    pub fn layout(msg_type: MsgType) -> &'static [VarField] {
+        use VarField::*;
+        match msg_type {
+            MsgType::Connect => &[ProtocolId, ProtocolVersion, Keepalive, ClientId, Sender],
+            MsgType::ConnectExtended => &[
+                ProtocolId,
+                ProtocolVersion,
+                Keepalive,
+                ClientId,
+                Sender,
+                Receiver,
+            ],
+            MsgType::ConnectAck => &[ConnectReturnCode],
+            MsgType::ConnectExtendedAck => &[ConnectReturnCode, ClientId, Sender, Receiver],
+            // DisConnectExtended carries the receiver like Data does; the
+            // legacy DisConnect is one byte shorter and has none.
+            MsgType::Data | MsgType::DisConnectExtended => &[ClientId, Sender, Receiver],
+            MsgType::DisConnect => &[ClientId, Sender],
+            MsgType::Undefined => &[],
         }
     }
 
-    pub fn from_bytes(buf: &[u8], msg_type: MsgType) -> VarHeader {
-        let size = Self::default_size(msg_type);
-        let mut var_header = match msg_type {
-            MsgType::ConnectExtended => Self::create_connect(buf),
-            MsgType::Connect => Self::create_connect_legacy(buf),
-            MsgType::ConnectExtendedAck => Self::create_connect_ack(buf),
-            MsgType::ConnectAck => Self::create_connect_ack_lagacy(buf),
-            MsgType::Data => Self::create_data(buf),
-            MsgType::DisConnectExtended => Self::create_disconnect(buf),
-            MsgType::DisConnect => Self::create_disconnect_legacy(buf),
-            _ => VarHeader::new(),
-        };
-        var_header.size = size.unwrap();
-        var_header
-    }
-    fn create_connect(buf: &[u8]) -> VarHeader {
-        let var_header = VarHeader::new()
-            .with_protocol_id(buf[0])
-            .with_protocol_version(buf[1])
-            .with_keepalive_lsb(buf[2])
-            .with_keepalive_msb(buf[3])
-            .with_client_id(u32::from_le_bytes(buf[4..8].try_into().unwrap()))
-            .with_sender(buf[8])
-            .with_receiver(buf[9]);
-        var_header
-    }
-    fn create_connect_legacy(buf: &[u8]) -> VarHeader {
-        VarHeader::new()
-            .with_protocol_id(buf[0])
-            .with_protocol_version(buf[1])
-            .with_keepalive_lsb(buf[2])
-            .with_keepalive_msb(buf[3])
-            .with_client_id(u32::from_le_bytes(buf[4..8].try_into().unwrap()))
-            .with_sender(buf[8])
-    }
-    fn create_connect_ack(buf: &[u8]) -> VarHeader {
-        VarHeader::new()
-            .with_connect_return_code(buf[0])
-            .with_client_id(u32::from_le_bytes(buf[1..5].try_into().unwrap()))
-            .with_sender(buf[5])
-            .with_receiver(buf[6])
-    }
-
-    fn create_connect_ack_lagacy(buf: &[u8]) -> VarHeader {
-        VarHeader::new().with_connect_return_code(buf[0])
-    }
-
-    fn create_data(buf: &[u8]) -> VarHeader {
-        VarHeader::new()
-            .with_client_id(u32::from_le_bytes(buf[0..4].try_into().unwrap()))
-            .with_sender(buf[4])
-            .with_receiver(buf[5])
-    }
-    fn create_disconnect(buf: &[u8]) -> VarHeader {
-        VarHeader::new()
-            .with_client_id(u32::from_le_bytes(buf[0..4].try_into().unwrap()))
-            .with_sender(buf[4])
-    }
-    fn create_disconnect_legacy(buf: &[u8]) -> VarHeader {
-        VarHeader::new()
-            .with_client_id(u32::from_le_bytes(buf[0..4].try_into().unwrap()))
-            .with_sender(buf[4])
-            .with_receiver(buf[5])
+    /// Parse the variable header of a `msg_type` frame from the start of `buf`.
+    /// Fields the frame type does not carry are `None`. Error offsets are
+    /// relative to the start of `buf`.
+    pub fn from_bytes(buf: &[u8], msg_type: MsgType) -> Result<VarHeader, IrsError> {
+        let size = Self::default_size(msg_type).ok_or(IrsError::UnknownMsgType {
+            offset: 0,
+            value: msg_type as u8,
+        })?;
+        ensure_len(buf, size as usize)?;
+        let mut var_header = VarHeader::empty();
+        let mut at = 0;
+        for field in Self::layout(msg_type) {
+            let b = &buf[at..at + field.size()];
+            match field {
+                VarField::ProtocolId => var_header.protocol_id = Some(b[0]),
+                VarField::ProtocolVersion => var_header.protocol_version = Some(b[0]),
+                VarField::Keepalive => {
+                    var_header.keepalive_lsb = Some(b[0]);
+                    var_header.keepalive_msb = Some(b[1]);
+                }
+                VarField::ClientId => {
+                    var_header.client_id = Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                }
+                VarField::Sender => var_header.sender = Some(b[0].into()),
+                VarField::Receiver => var_header.receiver = Some(b[0].into()),
+                VarField::ConnectReturnCode => var_header.connect_return_code = Some(b[0]),
+            }
+            at += field.size();
+        }
+        var_header.size = size;
+        Ok(var_header)
+    }
+}
+
+/// A field of the variable header. Multi-byte fields are little-endian.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum VarField {
+    ProtocolId,
+    ProtocolVersion,
+    /// Keep-alive interval in seconds.
+    Keepalive,
+    ClientId,
+    Sender,
+    Receiver,
+    ConnectReturnCode,
+}
+
+impl VarField {
+    pub fn name(self) -> &'static str {
+        match self {
+            VarField::ProtocolId => "protocol_id",
+            VarField::ProtocolVersion => "protocol_version",
+            VarField::Keepalive => "keepalive",
+            VarField::ClientId => "client_id",
+            VarField::Sender => "sender",
+            VarField::Receiver => "receiver",
+            VarField::ConnectReturnCode => "connect_return_code",
+        }
+    }
+    pub fn size(self) -> usize {
+        match self {
+            VarField::Keepalive => 2,
+            VarField::ClientId => 4,
+            _ => 1,
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tracing::info;
     #[test]
     fn test_header() {
         crate::init_tracing();
@@ -302,20 +449,202 @@ mod tests {
         header.crc = 0x1234;
         let bytes: Vec<u8> = header.into();
         info!("header bytes: {bytes:?}");
-        let h1 = Header::from(&bytes[..]);
+        let h1 = Header::try_from(&bytes[..]).unwrap();
         assert_eq!(h1.crc, 0x1234);
         assert_eq!(h1.payload_length, 20);
         assert_eq!(h1.msg_type, MsgType::ConnectExtended);
     }
     #[test]
+    fn test_header_errors() {
+        let bytes: Vec<u8> = Header::new().into();
+        assert!(matches!(
+            Header::try_from(&bytes[..5]),
+            Err(IrsError::Truncated {
+                offset: 5,
+                needed: 7,
+                available: 5
+            })
+        ));
+        let mut bad = bytes.clone();
+        bad[0] = 0x7f;
+        assert!(matches!(
+            Header::try_from(&bad[..]),
+            Err(IrsError::BadSoh {
+                offset: 0,
+                found: 0x7f
+            })
+        ));
+        let mut bad = bytes.clone();
+        bad[1] = 0x03;
+        assert!(matches!(
+            Header::try_from(&bad[..]),
+            Err(IrsError::BadStx { offset: 1, .. })
+        ));
+        let mut bad = bytes.clone();
+        bad[2] = 0x42;
+        assert!(matches!(
+            Header::try_from(&bad[..]),
+            Err(IrsError::UnknownMsgType {
+                offset: 2,
+                value: 0x42
+            })
+        ));
+    }
+    #[test]
     fn test_var_header_data() {
         crate::init_tracing();
-        let mut var_header = VarHeader::new().build(MsgType::Data);
+        let var_header = VarHeader::new().build(MsgType::Data).unwrap();
         assert_eq!(var_header.data.len(), 6);
-        let vh = VarHeader::from_bytes(&var_header.data, MsgType::Data);
+        let vh = VarHeader::from_bytes(&var_header.data, MsgType::Data).unwrap();
         assert_eq!(vh.client_id.unwrap(), DEFAULT_CLIENT_ID);
         assert_eq!(vh.sender.unwrap(), DEFAULT_SENDER);
         assert_eq!(vh.receiver.unwrap(), DEFAULT_RECEIVER);
         info!("vh: {vh:?}");
     }
+    #[test]
+    fn test_var_header_truncated() {
+        let var_header = VarHeader::new().build(MsgType::ConnectExtended).unwrap();
+        let err =
+            VarHeader::from_bytes(&var_header.data[..4], MsgType::ConnectExtended).unwrap_err();
+        assert!(matches!(
+            err,
+            IrsError::Truncated {
+                needed: 10,
+                available: 4,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_build_honours_fields() {
+        let types = [
+            MsgType::Connect,
+            MsgType::ConnectAck,
+            MsgType::Data,
+            MsgType::DisConnect,
+            MsgType::ConnectExtended,
+            MsgType::ConnectExtendedAck,
+            MsgType::DisConnectExtended,
+        ];
+        for msg_type in types {
+            let built = VarHeader::new()
+                .with_protocol_id(0x7a)
+                .with_protocol_version(0x05)
+                .with_keepalive(0x1234)
+                .with_client_id(0xdeadbeef)
+                .with_sender(DeviceCode::Unknown(0x21))
+                .with_receiver(DeviceCode::Backend)
+                .with_connect_return_code(0x03)
+                .build(msg_type)
+                .unwrap();
+            assert_eq!(built.data.len(), built.size as usize, "{msg_type:?}");
+            let vh = VarHeader::from_bytes(&built.data, msg_type).unwrap();
+            match msg_type {
+                MsgType::Connect | MsgType::ConnectExtended => {
+                    assert_eq!(vh.protocol_id, Some(0x7a));
+                    assert_eq!(vh.protocol_version, Some(0x05));
+                    assert_eq!(vh.keepalive(), Some(0x1234));
+                }
+                MsgType::ConnectAck | MsgType::ConnectExtendedAck => {
+                    assert_eq!(vh.connect_return_code, Some(0x03), "{msg_type:?}")
+                }
+                _ => {}
+            }
+            if msg_type != MsgType::ConnectAck {
+                assert_eq!(vh.client_id, Some(0xdeadbeef), "{msg_type:?}");
+                assert_eq!(vh.sender, Some(DeviceCode::Unknown(0x21)), "{msg_type:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_layout_sizes() {
+        let sizes = [
+            (MsgType::Connect, 9),
+            (MsgType::ConnectAck, 1),
+            (MsgType::Data, 6),
+            (MsgType::DisConnect, 5),
+            (MsgType::ConnectExtended, 10),
+            (MsgType::ConnectExtendedAck, 7),
+            (MsgType::DisConnectExtended, 6),
+        ];
+        for (msg_type, size) in sizes {
+            assert_eq!(
+                VarHeader::default_size(msg_type),
+                Some(size),
+                "{msg_type:?}"
+            );
+        }
+        assert_eq!(VarHeader::default_size(MsgType::Undefined), None);
+    }
+
+    #[test]
+    fn test_disconnect_receiver() {
+        let vh = VarHeader::new()
+            .with_client_id(7)
+            .with_sender(DeviceCode::MobileApp)
+            .with_receiver(DeviceCode::Backend);
+        let extended = vh.clone().build(MsgType::DisConnectExtended).unwrap();
+        assert_eq!(extended.data, [7, 0, 0, 0, 0x41, 0x42]);
+        let decoded = VarHeader::from_bytes(&extended.data, MsgType::DisConnectExtended).unwrap();
+        assert_eq!(decoded.receiver, Some(DeviceCode::Backend));
+
+        let legacy = vh.build(MsgType::DisConnect).unwrap();
+        assert_eq!(legacy.data, [7, 0, 0, 0, 0x41]);
+        let decoded = VarHeader::from_bytes(&legacy.data, MsgType::DisConnect).unwrap();
+        assert_eq!(decoded.client_id, Some(7));
+        assert_eq!(decoded.sender, Some(DeviceCode::MobileApp));
+        assert_eq!(decoded.receiver, None);
+    }
+
+    #[test]
+    fn test_absent_fields_decode_as_none() {
+        let vh = VarHeader::new();
+        let connect = vh.clone().build(MsgType::Connect).unwrap();
+        let decoded = VarHeader::from_bytes(&connect.data, MsgType::Connect).unwrap();
+        assert_eq!(decoded.sender, vh.sender);
+        assert_eq!(decoded.receiver, None);
+        assert_eq!(decoded.connect_return_code, None);
+
+        let ack = vh.build(MsgType::ConnectAck).unwrap();
+        let decoded = VarHeader::from_bytes(&ack.data, MsgType::ConnectAck).unwrap();
+        assert_eq!(decoded.connect_return_code, Some(0x09));
+        assert_eq!(decoded.client_id, None);
+        assert_eq!(decoded.sender, None);
+        assert_eq!(decoded.receiver, None);
+        assert_eq!(decoded.keepalive(), None);
+    }
+
+    #[test]
+    fn test_build_missing_field() {
+        let mut vh = VarHeader::new();
+        vh.receiver = None;
+        assert!(vh.clone().build(MsgType::DisConnect).is_ok());
+        assert!(matches!(
+            vh.build(MsgType::Data),
+            Err(IrsError::MissingField {
+                msg_type: MsgType::Data,
+                field: "receiver"
+            })
+        ));
+        assert!(matches!(
+            VarHeader::new().build(MsgType::Undefined),
+            Err(IrsError::UnknownMsgType { .. })
+        ));
+    }
+
+    #[test]
+    fn test_device_code() {
+        for byte in 0..=u8::MAX {
+            assert_eq!(u8::from(DeviceCode::from(byte)), byte);
+        }
+        assert_eq!(
+            DeviceCode::from(0x4d),
+            DeviceCode::MowerMainBoardApplicationSw
+        );
+        assert_eq!(DeviceCode::from(0x21), DeviceCode::Unknown(0x21));
+        assert_eq!(DEFAULT_RECEIVER.to_string(), "mower main board");
+        assert_eq!(DeviceCode::Unknown(0x21).to_string(), "device 0x21");
+    }
 }
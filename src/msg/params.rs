@@ -1,45 +1,139 @@
-use std::{any::Any, fmt::Debug, process::id};
+use std::{any::Any, fmt};
 
-use serde::{Deserialize, Serialize, ser::Error};
+use tracing::debug;
 
-use crate::msg::serialization::{self, BinaryDeserializer, BinarySerializeError, BinarySerializer};
+use crate::error::{IrsError, ensure_len, len_u16};
+use crate::msg::serialization::BinarySerializeError;
+
+pub use irs_derive::IrsParam;
 
 pub mod data;
+mod registry;
+
+pub use data::ParamId;
+pub use registry::ParamRegistry;
+
+/// A parameter's data, with its ID and direction. Usually derived with
+/// `#[derive(IrsParam)]`, which also registers the type for decoding.
+pub trait IrsParam: fmt::Debug + Send + Sync + 'static {
+    const ID: u16;
+    const NAME: &'static str;
+    /// ID of the request parameter this one answers, if it is a response.
+    const RESPONSE_TO: Option<u16>;
+
+    fn encode(&self) -> Result<Vec<u8>, BinarySerializeError>;
+    fn decode(bytes: &[u8]) -> Result<Self, BinarySerializeError>
+    where
+        Self: Sized;
+}
+
+/// Object-safe view of an [`IrsParam`], as held by [`ParamPayload::Typed`].
+pub trait DynParam: fmt::Debug + Send + Sync {
+    fn id(&self) -> u16;
+    fn name(&self) -> &'static str;
+    fn encode(&self) -> Result<Vec<u8>, BinarySerializeError>;
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: IrsParam> DynParam for T {
+    fn id(&self) -> u16 {
+        T::ID
+    }
+    fn name(&self) -> &'static str {
+        T::NAME
+    }
+    fn encode(&self) -> Result<Vec<u8>, BinarySerializeError> {
+        IrsParam::encode(self)
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
 
-#[repr(u16)]
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub enum ParamId {
-    GetCuttingHeightReq = 470,
-    GetCuttingHeightResp,
+/// Registration record of a parameter type, submitted by
+/// `#[derive(IrsParam)]` and held by [`ParamRegistry`].
+#[derive(Debug, Clone, Copy)]
+pub struct ParamInfo {
+    pub id: u16,
+    pub name: &'static str,
+    pub response_to: Option<u16>,
+    pub decode: fn(&[u8]) -> Result<ParamPayload, BinarySerializeError>,
 }
 
+impl ParamInfo {
+    pub const fn of<T: IrsParam>() -> Self {
+        ParamInfo {
+            id: T::ID,
+            name: T::NAME,
+            response_to: T::RESPONSE_TO,
+            decode: decode_typed::<T>,
+        }
+    }
+}
+
+fn decode_typed<T: IrsParam>(bytes: &[u8]) -> Result<ParamPayload, BinarySerializeError> {
+    Ok(ParamPayload::new(T::decode(bytes)?))
+}
+
+inventory::collect!(ParamInfo);
+
 #[derive(Debug)]
 pub enum ParamPayload {
-    P470,
-    P471(data::Param471),
+    /// Data of a registered [`IrsParam`] type.
+    Typed(Box<dyn DynParam>),
+    /// Data of a parameter the decoding [`ParamRegistry`] does not know,
+    /// kept as received.
+    Raw { id: u16, bytes: Vec<u8> },
 }
 
 impl ParamPayload {
-    pub fn deconde(id: u16, bytes: &[u8]) -> Result<Self, BinarySerializeError> {
-        let p = match id {
-            470 => {
-                return Ok(ParamPayload::P470);
-            },
-            471 => {
-                let data = serialization::deserialize(&bytes)?;
-                return Ok(ParamPayload::P471(data));
-            }
-            _ => {
-                return Err(BinarySerializeError::custom("not support"));
-            }
-        };
+    pub fn new<T: IrsParam>(data: T) -> Self {
+        ParamPayload::Typed(Box::new(data))
+    }
+    /// Undecoded data for parameter `id`, encoded exactly as given.
+    pub fn raw(id: u16, bytes: impl Into<Vec<u8>>) -> Self {
+        ParamPayload::Raw {
+            id,
+            bytes: bytes.into(),
+        }
     }
-    pub fn encode(&self) -> Vec<u8> {
+    /// The undecoded bytes, if this is [`ParamPayload::Raw`].
+    pub fn as_raw(&self) -> Option<&[u8]> {
         match self {
-            ParamPayload::P470 => Vec::new(),
-            ParamPayload::P471(data) => serialization::serialize(&data).unwrap(),
+            ParamPayload::Typed(_) => None,
+            ParamPayload::Raw { bytes, .. } => Some(bytes),
         }
     }
+    /// The data as `T`, if that is what it holds.
+    pub fn downcast_ref<T: IrsParam>(&self) -> Option<&T> {
+        match self {
+            ParamPayload::Typed(data) => data.as_any().downcast_ref(),
+            ParamPayload::Raw { .. } => None,
+        }
+    }
+    pub fn id(&self) -> u16 {
+        match self {
+            ParamPayload::Typed(data) => data.id(),
+            ParamPayload::Raw { id, .. } => *id,
+        }
+    }
+    /// Decode the data part of parameter `id` with the global registry,
+    /// see [`ParamRegistry::decode`].
+    pub fn deconde(id: u16, bytes: &[u8]) -> Result<Self, IrsError> {
+        Ok(ParamRegistry::global().decode(id, bytes))
+    }
+    pub fn encode(&self) -> Result<Vec<u8>, BinarySerializeError> {
+        match self {
+            ParamPayload::Typed(data) => data.encode(),
+            ParamPayload::Raw { bytes, .. } => Ok(bytes.clone()),
+        }
+    }
+}
+
+impl<T: IrsParam> From<T> for ParamPayload {
+    fn from(data: T) -> Self {
+        ParamPayload::new(data)
+    }
 }
 
 pub struct Param {
@@ -48,47 +142,174 @@ pub struct Param {
 }
 
 impl Param {
+    pub const HEADER_SIZE: usize = 4;
+
     pub fn new(id: u16, data: ParamPayload) -> Self {
         Param { id, data }
     }
-    pub fn to_bytes(self) -> Vec<u8> {
+    /// A parameter carrying `data` under its type's ID.
+    pub fn of<T: IrsParam>(data: T) -> Self {
+        Param::new(T::ID, ParamPayload::new(data))
+    }
+    /// The data as `T`, if that is what it holds.
+    pub fn get<T: IrsParam>(&self) -> Option<&T> {
+        self.data.downcast_ref()
+    }
+    /// Encode ID, length and data. Fails if the data does not serialize or
+    /// is too long for the length field.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, IrsError> {
         let mut buf = Vec::new();
         buf.extend_from_slice(&self.id.to_le_bytes());
-        let data_bytes = self.data.encode();
-        let len = data_bytes.len() as u16;
-        println!("data_bytes len: {len}");
+        let data_bytes = self.data.encode().map_err(IrsError::PayloadEncode)?;
+        let len = len_u16("parameter data", data_bytes.len())?;
+        debug!("data_bytes len: {len}");
         buf.extend_from_slice(&len.to_le_bytes());
         buf.extend_from_slice(&data_bytes);
-        buf
+        Ok(buf)
+    }
+    /// Decode one parameter (ID, length and data) from the start of `bytes`
+    /// with the global registry.
+    /// Error offsets are relative to the start of `bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, IrsError> {
+        Self::from_bytes_in(bytes, ParamRegistry::global())
     }
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BinarySerializeError> {
+    /// Like [`Param::from_bytes`], decoding the data with `registry`.
+    pub fn from_bytes_in(bytes: &[u8], registry: &ParamRegistry) -> Result<Self, IrsError> {
+        ensure_len(bytes, Self::HEADER_SIZE)?;
         let id = u16::from_le_bytes([bytes[0], bytes[1]]);
-        let len = u16::from_le_bytes([bytes[2], bytes[3]]);
-        println!("id: {id}, len: {len}, bytes len: {}", bytes.len());
-        let data = ParamPayload::deconde(id, &bytes[4..(4 + len) as usize])?;
+        let len = u16::from_le_bytes([bytes[2], bytes[3]]) as usize;
+        debug!("id: {id}, len: {len}, bytes len: {}", bytes.len());
+        ensure_len(bytes, Self::HEADER_SIZE + len)?;
+        let data = registry.decode(id, &bytes[Self::HEADER_SIZE..Self::HEADER_SIZE + len]);
         Ok(Param { id, data })
     }
 }
 
-pub fn test_param() {
-    let param = ParamPayload::P471(data::Param471 {
-        return_code: 0,
-        current_cutting_height: 0,
-        default_cutting_height: 0,
-        information: 1,
-    });
-    let bytes = param.encode();
-    let p1 = ParamPayload::deconde(471, &bytes);
-    let param = Param::new(
-        471,
-        ParamPayload::P471(data::Param471 {
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize, IrsParam)]
+    #[irs(id = 0x7001, response_to = 0x7000)]
+    struct VendorStatus {
+        level: u16,
+        flags: u8,
+    }
+
+    #[test]
+    fn test_derived_param() {
+        assert_eq!(VendorStatus::ID, 0x7001);
+        assert_eq!(VendorStatus::NAME, "VendorStatus");
+        assert_eq!(data::Param471::NAME, "GetCuttingHeightResp");
+        let registry = ParamRegistry::global();
+        assert_eq!(registry.get(0x7001).unwrap().response_to, Some(0x7000));
+        assert_eq!(registry.responses_to(0x7000).collect::<Vec<_>>(), [0x7001]);
+
+        let status = VendorStatus {
+            level: 0x0102,
+            flags: 3,
+        };
+        let bytes = Param::of(status).to_bytes().unwrap();
+        assert_eq!(bytes, [0x01, 0x70, 0x03, 0x00, 0x02, 0x01, 0x03]);
+        let param = Param::from_bytes(&bytes).unwrap();
+        assert_eq!(param.data.id(), 0x7001);
+        assert_eq!(
+            param.get::<VendorStatus>(),
+            Some(&VendorStatus {
+                level: 0x0102,
+                flags: 3
+            })
+        );
+        assert!(param.get::<data::Param471>().is_none());
+    }
+
+    #[test]
+    fn test_param() {
+        let param = ParamPayload::new(data::Param471 {
             return_code: 0,
             current_cutting_height: 0,
             default_cutting_height: 0,
             information: 1,
-        }),
-    );
-    let bytes = param.to_bytes();
-    
-    let p2 = Param::from_bytes(&bytes).unwrap();
+        });
+        let bytes = param.encode().unwrap();
+        assert!(ParamPayload::deconde(471, &bytes).is_ok());
+        let param = Param::new(
+            471,
+            ParamPayload::new(data::Param471 {
+                return_code: 0,
+                current_cutting_height: 0,
+                default_cutting_height: 0,
+                information: 1,
+            }),
+        );
+        let bytes = param.to_bytes().unwrap();
+
+        let p2 = Param::from_bytes(&bytes).unwrap();
+        assert_eq!(p2.id, 471);
+    }
+
+    #[derive(Debug)]
+    struct Unencodable;
+
+    impl IrsParam for Unencodable {
+        const ID: u16 = 0x7002;
+        const NAME: &'static str = "Unencodable";
+        const RESPONSE_TO: Option<u16> = None;
+
+        fn encode(&self) -> Result<Vec<u8>, BinarySerializeError> {
+            Err(serde::ser::Error::custom("no encoding"))
+        }
+        fn decode(_: &[u8]) -> Result<Self, BinarySerializeError> {
+            Ok(Unencodable)
+        }
+    }
+
+    #[test]
+    fn test_encode_errors() {
+        assert!(matches!(
+            Param::of(Unencodable).to_bytes(),
+            Err(IrsError::PayloadEncode(_))
+        ));
+        let long = Param::new(0x1234, ParamPayload::raw(0x1234, vec![0; 0x10000]));
+        assert!(matches!(
+            long.to_bytes(),
+            Err(IrsError::TooLong {
+                field: "parameter data",
+                len: 0x10000
+            })
+        ));
+        let longest = Param::new(0x1234, ParamPayload::raw(0x1234, vec![0; 0xffff]));
+        assert_eq!(longest.to_bytes().unwrap()[2..4], [0xff, 0xff]);
+    }
+
+    #[test]
+    fn test_param_errors() {
+        assert!(matches!(
+            Param::from_bytes(&[0xd7, 0x01]),
+            Err(IrsError::Truncated { offset: 2, .. })
+        ));
+        // 471 announcing 4 data bytes but carrying 2
+        assert!(matches!(
+            Param::from_bytes(&[0xd7, 0x01, 0x04, 0x00, 0x01, 0x02]),
+            Err(IrsError::Truncated {
+                needed: 8,
+                available: 6,
+                ..
+            })
+        ));
+        // 471 whose length field is too short for Param471 is kept raw
+        assert!(matches!(
+            Param::from_bytes(&[0xd7, 0x01, 0x02, 0x00, 0x01, 0x02])
+                .unwrap()
+                .data,
+            ParamPayload::Raw { id: 471, .. }
+        ));
+        let unknown = Param::from_bytes(&[0x34, 0x12, 0x01, 0x00, 0xab]).unwrap();
+        assert!(matches!(
+            unknown.data,
+            ParamPayload::Raw { id: 0x1234, ref bytes } if bytes == &[0xab]
+        ));
+    }
 }
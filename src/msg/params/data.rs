@@ -1,9 +1,47 @@
+// @generated by irs-codegen from spec/params.toml, do not edit.
+// Regenerate with `cargo run -p irs-codegen`.
+
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+use super::IrsParam;
+
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ParamId {
+    GetCuttingHeightReq = 470,
+    GetCuttingHeightResp = 471,
+}
+
+impl TryFrom<u16> for ParamId {
+    type Error = ();
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            470 => Ok(ParamId::GetCuttingHeightReq),
+            471 => Ok(ParamId::GetCuttingHeightResp),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<ParamId> for u16 {
+    fn from(id: ParamId) -> Self {
+        id as u16
+    }
+}
+
+/// Read the cutting height.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, IrsParam)]
+#[irs(id = 470, name = "GetCuttingHeightReq")]
+pub struct Param470;
+
+/// Cutting height reported by the mower.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, IrsParam)]
+#[irs(id = 471, response_to = 470, name = "GetCuttingHeightResp")]
 pub struct Param471 {
     pub return_code: u8,
+    /// Unit: mm.
     pub default_cutting_height: u8,
+    /// Unit: mm.
     pub current_cutting_height: u8,
     pub information: u8,
 }
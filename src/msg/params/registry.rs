@@ -0,0 +1,195 @@
+use std::{collections::BTreeMap, sync::OnceLock};
+
+use tracing::debug;
+
+use super::{IrsParam, ParamInfo, ParamPayload};
+
+/// Maps parameter IDs to their names, pairing and decoders.
+///
+/// [`ParamRegistry::default`] holds every type deriving
+/// [`IrsParam`](super::IrsParam) linked into the program, and is what the
+/// plain `from_bytes` decoders use (via [`ParamRegistry::global`]).
+/// Applications add their own parameters, e.g. vendor-specific ones, with
+/// [`ParamRegistry::register`] or [`ParamRegistry::insert`] and pass the
+/// registry to the `from_bytes_in` decoders. IDs not in the registry decode
+/// to [`ParamPayload::Raw`].
+#[derive(Debug, Clone)]
+pub struct ParamRegistry {
+    params: BTreeMap<u16, ParamInfo>,
+}
+
+impl Default for ParamRegistry {
+    fn default() -> Self {
+        let mut registry = Self::new();
+        for info in inventory::iter::<ParamInfo> {
+            registry.insert(*info);
+        }
+        registry
+    }
+}
+
+impl ParamRegistry {
+    /// An empty registry; every parameter decodes to [`ParamPayload::Raw`].
+    pub fn new() -> Self {
+        ParamRegistry {
+            params: BTreeMap::new(),
+        }
+    }
+
+    /// The default registry, built once.
+    pub fn global() -> &'static ParamRegistry {
+        static GLOBAL: OnceLock<ParamRegistry> = OnceLock::new();
+        GLOBAL.get_or_init(ParamRegistry::default)
+    }
+
+    /// Register `T`, replacing whatever was registered under its ID.
+    pub fn register<T: IrsParam>(&mut self) -> &mut Self {
+        self.insert(ParamInfo::of::<T>())
+    }
+
+    /// Register a parameter described by hand, e.g. with a decoder that does
+    /// not go through serde. Replaces whatever was registered under its ID.
+    pub fn insert(&mut self, info: ParamInfo) -> &mut Self {
+        self.params.insert(info.id, info);
+        self
+    }
+
+    pub fn with<T: IrsParam>(mut self) -> Self {
+        self.register::<T>();
+        self
+    }
+
+    pub fn get(&self, id: u16) -> Option<&ParamInfo> {
+        self.params.get(&id)
+    }
+
+    pub fn name(&self, id: u16) -> Option<&'static str> {
+        self.get(id).map(|info| info.name)
+    }
+
+    /// IDs of the parameters registered as responses to request parameter `id`.
+    pub fn responses_to(&self, id: u16) -> impl Iterator<Item = u16> + '_ {
+        self.params
+            .values()
+            .filter(move |info| info.response_to == Some(id))
+            .map(|info| info.id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ParamInfo> {
+        self.params.values()
+    }
+
+    /// Decode the data part of parameter `id`.
+    ///
+    /// Data that does not decode or would not re-encode to the same bytes,
+    /// such as an unknown ID or a known one carrying fields added by newer
+    /// firmware, is kept as [`ParamPayload::Raw`] so that forwarding it
+    /// loses nothing.
+    pub fn decode(&self, id: u16, bytes: &[u8]) -> ParamPayload {
+        let raw = || ParamPayload::Raw {
+            id,
+            bytes: bytes.to_vec(),
+        };
+        let Some(info) = self.get(id) else {
+            return raw();
+        };
+        let data = match (info.decode)(bytes) {
+            Ok(data) => data,
+            Err(e) => {
+                debug!("{} does not decode, keeping it raw: {e}", info.name);
+                return raw();
+            }
+        };
+        if data.encode().ok().as_deref() != Some(bytes) {
+            debug!("{} does not round-trip, keeping it raw", info.name);
+            return raw();
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::{
+        params::{Param, data::Param471},
+        serialization::BinarySerializeError,
+    };
+
+    /// A vendor parameter implemented by hand, so it is not in the default
+    /// registry: its data is big-endian u16s, which serde would not produce.
+    #[derive(Debug)]
+    struct VendorLevels(Vec<u16>);
+
+    impl IrsParam for VendorLevels {
+        const ID: u16 = 0x7200;
+        const NAME: &'static str = "VendorLevels";
+        const RESPONSE_TO: Option<u16> = None;
+
+        fn encode(&self) -> Result<Vec<u8>, BinarySerializeError> {
+            Ok(self.0.iter().flat_map(|v| v.to_be_bytes()).collect())
+        }
+        fn decode(bytes: &[u8]) -> Result<Self, BinarySerializeError> {
+            let levels = bytes.chunks_exact(2);
+            if !levels.remainder().is_empty() {
+                return Err(serde::de::Error::custom("odd length"));
+            }
+            Ok(VendorLevels(
+                levels.map(|b| u16::from_be_bytes([b[0], b[1]])).collect(),
+            ))
+        }
+    }
+
+    #[test]
+    fn test_registry_lookup_and_decode() {
+        let global = ParamRegistry::global();
+        assert_eq!(global.name(471), Some("GetCuttingHeightResp"));
+        assert_eq!(global.responses_to(470).collect::<Vec<_>>(), [471]);
+
+        let empty = ParamRegistry::new();
+        let bytes = [0, 30, 45, 0];
+        assert!(matches!(
+            empty.decode(471, &bytes),
+            ParamPayload::Raw { id: 471, .. }
+        ));
+        let registry = ParamRegistry::new().with::<Param471>();
+        let payload = registry.decode(471, &bytes);
+        assert_eq!(
+            payload
+                .downcast_ref::<Param471>()
+                .unwrap()
+                .current_cutting_height,
+            45
+        );
+        assert_eq!(registry.iter().count(), 1);
+    }
+
+    #[test]
+    fn test_undecodable_data_kept_raw() {
+        let registry = ParamRegistry::default().with::<VendorLevels>();
+        // 471 one byte short, and a vendor parameter of odd length
+        for (id, bytes) in [(471, &[0, 30, 45][..]), (0x7200, &[1, 2, 3][..])] {
+            assert!(matches!(
+                registry.decode(id, bytes),
+                ParamPayload::Raw { id: raw_id, bytes: ref raw } if raw_id == id && raw == bytes
+            ));
+        }
+        let short = [0xd7, 0x01, 0x03, 0x00, 0, 30, 45];
+        let param = Param::from_bytes(&short).unwrap();
+        assert!(param.get::<Param471>().is_none());
+        assert_eq!(param.to_bytes().unwrap(), short);
+    }
+
+    #[test]
+    fn test_vendor_param() {
+        let registry = ParamRegistry::default().with::<VendorLevels>();
+        let bytes = [0x00, 0x72, 0x04, 0x00, 0x01, 0x02, 0x00, 0xff];
+        let param = Param::from_bytes_in(&bytes, &registry).unwrap();
+        assert_eq!(registry.name(0x7200), Some("VendorLevels"));
+        assert_eq!(param.get::<VendorLevels>().unwrap().0, [0x0102, 0x00ff]);
+        assert_eq!(param.to_bytes().unwrap(), bytes);
+        // the global registry has never heard of it
+        let raw = Param::from_bytes(&bytes).unwrap();
+        assert!(matches!(raw.data, ParamPayload::Raw { id: 0x7200, .. }));
+    }
+}
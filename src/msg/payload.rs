@@ -1,4 +1,9 @@
-use super::params;
+use super::{
+    cipher::{SEQ_SIZE, SessionCipher, TAG_SIZE},
+    header::{CRC16, DeviceCode, Header},
+    params,
+};
+use crate::error::{IrsError, ensure_len, len_u16};
 use tracing::debug;
 ///\brief Payload format.
 ///\details
@@ -10,18 +15,38 @@ use tracing::debug;
 ///    | 8 bits | 16 bits | 16 bits | 16 bits  | x bits    | ... |                | 16 bits |
 ///    |--------+---------+---------+----------+-----------+-----+----------------+---------|
 /// ```
+///
+/// UeLen counts the parameter bytes sent in clear. Whatever follows them up
+/// to the CRC is the sealed section, see [`super::cipher`]; decoded without
+/// the key it is kept in [`Payload::encrypted`].
 pub struct Payload {
     pub msg_id: u8,
     pub unencrypted_length: u16,
     pub params: Vec<params::Param>,
+    /// Index of the first parameter [`Payload::to_bytes_sealed`] encrypts;
+    /// the ones before it stay in clear.
+    pub encrypt_from: usize,
+    /// Sealed section of a payload decoded without the key, written after
+    /// the clear parameters.
+    pub encrypted: Vec<u8>,
     pub crc: u16,
 }
+impl Default for Payload {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl Payload {
+    /// MsgId, UeLen and CRC, i.e. a payload without parameters.
+    pub const MIN_SIZE: usize = 5;
+
     pub fn new() -> Self {
         Payload {
             msg_id: 0,
             unencrypted_length: 0,
             params: Vec::new(),
+            encrypt_from: 0,
+            encrypted: Vec::new(),
             crc: 0,
         }
     }
@@ -32,45 +57,198 @@ impl Payload {
     pub fn get_params(&self) -> &Vec<params::Param> {
         &self.params
     }
-    pub fn calc_crc(&mut self, buf: &[u8]) {
-        todo!()
+    /// Compute and store the CRC-16/ARC over everything preceding the CRC field.
+    pub fn calc_crc(&mut self) -> Result<u16, IrsError> {
+        self.crc = CRC16.checksum(&self.body_bytes(None)?);
+        Ok(self.crc)
+    }
+
+    /// Encode the payload, trailing CRC computed over the encoded bytes.
+    /// Parameters are all written in clear, followed by [`Payload::encrypted`].
+    /// Fails if a parameter does not encode or UeLen would overflow.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, IrsError> {
+        self.encode(None)
+    }
+
+    /// Like [`Payload::to_bytes`], sealing the parameters from
+    /// [`Payload::encrypt_from`] on as the next frame `cipher` sends for
+    /// `sender`. The sealed section is written even if there is nothing to
+    /// encrypt, so that the whole payload is authenticated, together with
+    /// `header` and the encoded `var_header` of its frame. The
+    /// `payload_length` and CRC of `header` are ignored.
+    pub fn to_bytes_sealed(
+        &self,
+        cipher: &SessionCipher,
+        sender: DeviceCode,
+        header: &Header,
+        var_header: &[u8],
+    ) -> Result<Vec<u8>, IrsError> {
+        if !self.encrypted.is_empty() {
+            return Err(IrsError::AlreadySealed);
+        }
+        self.encode(Some(Sealing {
+            cipher,
+            sender,
+            header,
+            var_header,
+        }))
     }
 
-    pub fn to_bytes(self) -> Vec<u8> {
+    fn encode(&self, seal: Option<Sealing<'_>>) -> Result<Vec<u8>, IrsError> {
+        let mut buf = self.body_bytes(seal)?;
+        let crc = CRC16.checksum(&buf);
+        buf.extend_from_slice(&crc.to_le_bytes());
+        Ok(buf)
+    }
+
+    fn body_bytes(&self, seal: Option<Sealing<'_>>) -> Result<Vec<u8>, IrsError> {
+        let clear = match seal {
+            Some(_) => self.encrypt_from.min(self.params.len()),
+            None => self.params.len(),
+        };
         let mut buf = Vec::new();
         buf.push(self.msg_id);
-        let mut total_param_length = 0usize;
-        let param_bytes_list: Vec<Vec<u8>> = self.params.into_iter().map(|param| {
-            let id = param.id;
-            let param_bytes = param.to_bytes();
-            debug!("id: {id}, len: {}", param_bytes.len());
+        let param_bytes = Self::params_bytes(&self.params[..clear])?;
+        let ue_len = len_u16("clear parameters", param_bytes.len())?;
+        buf.extend_from_slice(&ue_len.to_le_bytes());
+        buf.extend_from_slice(&param_bytes);
+        match seal {
+            Some(seal) => {
+                let data = Self::params_bytes(&self.params[clear..])?;
+                let len = buf.len() + SEQ_SIZE + data.len() + TAG_SIZE + 2;
+                let aad = seal.aad(len_u16("payload", len)?, &buf);
+                buf.extend_from_slice(&seal.cipher.seal(seal.sender, &aad, data)?);
+            }
+            None => buf.extend_from_slice(&self.encrypted),
+        }
+        Ok(buf)
+    }
+
+    fn params_bytes(params: &[params::Param]) -> Result<Vec<u8>, IrsError> {
+        let mut buf = Vec::new();
+        for param in params {
+            let param_bytes = param.to_bytes()?;
+            debug!("id: {}, len: {}", param.id, param_bytes.len());
             debug!("param bytes: {param_bytes:?}");
-            total_param_length += param_bytes.len();
-            param_bytes
-        }).collect();
-        buf.extend_from_slice(&(total_param_length as u16).to_le_bytes());
-        param_bytes_list.into_iter().for_each(|param_bytes| {
             buf.extend_from_slice(&param_bytes);
-        });
-        // TODO: calculate CRC
-        buf.extend_from_slice(&self.crc.to_le_bytes());
-        buf
+        }
+        Ok(buf)
+    }
+
+    fn params_from_bytes(
+        bytes: &[u8],
+        registry: &params::ParamRegistry,
+    ) -> Result<Vec<params::Param>, IrsError> {
+        let mut params = Vec::new();
+        let mut index = 0;
+        while index < bytes.len() {
+            let param =
+                params::Param::from_bytes_in(&bytes[index..], registry).map_err(|e| e.at(index))?;
+            // from_bytes has checked that the length field is present
+            let len = u16::from_le_bytes([bytes[index + 2], bytes[index + 3]]);
+            index += params::Param::HEADER_SIZE + len as usize;
+            params.push(param);
+        }
+        Ok(params)
+    }
+    /// Decode a payload occupying the whole of `bytes`, trailing CRC included,
+    /// with the global parameter registry.
+    /// The CRC is stored but not checked, see [`Payload::crc_over`].
+    /// Error offsets are relative to the start of `bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, IrsError> {
+        Self::from_bytes_in(bytes, params::ParamRegistry::global())
+    }
+    /// Like [`Payload::from_bytes`], decoding parameters with `registry`.
+    pub fn from_bytes_in(bytes: &[u8], registry: &params::ParamRegistry) -> Result<Self, IrsError> {
+        Self::decode(bytes, registry, None)
     }
-    pub fn from_bytes(bytes: &[u8]) -> Self {
+    /// Like [`Payload::from_bytes_in`], opening the sealed section as a frame
+    /// `cipher` receives from `sender` and appending the parameters it holds.
+    /// `header` and `var_header` are those of the frame, as received.
+    /// Fails with [`IrsError::Unauthenticated`] if the section is missing or
+    /// the frame was altered.
+    pub fn from_bytes_sealed(
+        bytes: &[u8],
+        registry: &params::ParamRegistry,
+        cipher: &SessionCipher,
+        sender: DeviceCode,
+        header: &Header,
+        var_header: &[u8],
+    ) -> Result<Self, IrsError> {
+        Self::decode(
+            bytes,
+            registry,
+            Some(Sealing {
+                cipher,
+                sender,
+                header,
+                var_header,
+            }),
+        )
+    }
+
+    fn decode(
+        bytes: &[u8],
+        registry: &params::ParamRegistry,
+        open: Option<Sealing<'_>>,
+    ) -> Result<Self, IrsError> {
+        ensure_len(bytes, Self::MIN_SIZE)?;
         let mut payload = Payload::new();
         payload.msg_id = bytes[0];
         payload.unencrypted_length = u16::from_le_bytes([bytes[1], bytes[2]]);
-        
-        let mut index = 3;
-        while index < bytes.len() - 2 {
-            let id = u16::from_le_bytes([bytes[index], bytes[index + 1]]);
-            let len = u16::from_le_bytes([bytes[index + 2], bytes[index + 3]]);
-            let param_bytes = &bytes[index..(index + 4 + len as usize)];
-            let param = params::Param::from_bytes(param_bytes).unwrap();
-            payload.params.push(param);
-            index += 4 + len as usize;
+
+        let crc_at = bytes.len() - 2;
+        let encrypted_at = 3 + payload.unencrypted_length as usize;
+        if encrypted_at > crc_at {
+            return Err(IrsError::LengthMismatch {
+                offset: 1,
+                expected: payload.unencrypted_length as usize,
+                actual: crc_at - 3,
+            });
         }
-        payload.crc = u16::from_le_bytes([bytes[bytes.len() - 2], bytes[bytes.len() - 1]]);
-        payload
+        payload.params =
+            Self::params_from_bytes(&bytes[3..encrypted_at], registry).map_err(|e| e.at(3))?;
+        payload.encrypt_from = payload.params.len();
+        match open {
+            Some(open) => {
+                let sealed = &bytes[encrypted_at..crc_at];
+                let aad = open.aad(open.header.payload_length, &bytes[..encrypted_at]);
+                let data = open.cipher.open(open.sender, &aad, sealed)?;
+                let params = Self::params_from_bytes(&data, registry)
+                    .map_err(|e| e.at(encrypted_at + SEQ_SIZE))?;
+                payload.params.extend(params);
+            }
+            None => payload.encrypted = bytes[encrypted_at..crc_at].to_vec(),
+        }
+        payload.crc = u16::from_le_bytes([bytes[crc_at], bytes[crc_at + 1]]);
+        Ok(payload)
+    }
+
+    /// CRC of a received payload: computed over all of `bytes` but the last two.
+    pub fn crc_over(bytes: &[u8]) -> u16 {
+        CRC16.checksum(&bytes[..bytes.len().saturating_sub(2)])
+    }
+}
+
+/// What sealing or opening a payload needs besides its bytes.
+struct Sealing<'a> {
+    cipher: &'a SessionCipher,
+    sender: DeviceCode,
+    header: &'a Header,
+    var_header: &'a [u8],
+}
+
+impl Sealing<'_> {
+    /// Associated data of a `payload_length` payload starting with `clear`:
+    /// the header up to its CRC, the var header and `clear`, so that a frame
+    /// retyped or readdressed on the way fails to open.
+    fn aad(&self, payload_length: u16, clear: &[u8]) -> Vec<u8> {
+        let mut header = self.header.clone();
+        header.payload_length = payload_length;
+        let mut aad: Vec<u8> = header.into();
+        aad.truncate(Header::CRC_OFFSET);
+        aad.extend_from_slice(self.var_header);
+        aad.extend_from_slice(clear);
+        aad
     }
 }
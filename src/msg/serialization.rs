@@ -1,4 +1,4 @@
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use std::fmt;
 
 #[derive(Debug)]
@@ -48,6 +48,12 @@ pub struct BinaryDeserializer<'a> {
     pos: usize,
 }
 
+impl Default for BinarySerializer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BinarySerializer {
     pub fn new() -> Self {
         Self { output: Vec::new() }
@@ -71,7 +77,7 @@ impl BinarySerializer {
     }
 }
 
-impl<'a> serde::Serializer for &'a mut BinarySerializer {
+impl serde::Serializer for &mut BinarySerializer {
     type Ok = ();
     type Error = BinarySerializeError;
     type SerializeSeq = Self;
@@ -253,7 +259,7 @@ impl<'a> serde::Serializer for &'a mut BinarySerializer {
     }
 }
 
-impl<'a> serde::ser::SerializeSeq for &'a mut BinarySerializer {
+impl serde::ser::SerializeSeq for &mut BinarySerializer {
     type Ok = ();
     type Error = BinarySerializeError;
 
@@ -267,7 +273,7 @@ impl<'a> serde::ser::SerializeSeq for &'a mut BinarySerializer {
     }
 }
 
-impl<'a> serde::ser::SerializeTuple for &'a mut BinarySerializer {
+impl serde::ser::SerializeTuple for &mut BinarySerializer {
     type Ok = ();
     type Error = BinarySerializeError;
 
@@ -280,7 +286,7 @@ impl<'a> serde::ser::SerializeTuple for &'a mut BinarySerializer {
     }
 }
 
-impl<'a> serde::ser::SerializeTupleStruct for &'a mut BinarySerializer {
+impl serde::ser::SerializeTupleStruct for &mut BinarySerializer {
     type Ok = ();
     type Error = BinarySerializeError;
 
@@ -293,7 +299,7 @@ impl<'a> serde::ser::SerializeTupleStruct for &'a mut BinarySerializer {
     }
 }
 
-impl<'a> serde::ser::SerializeTupleVariant for &'a mut BinarySerializer {
+impl serde::ser::SerializeTupleVariant for &mut BinarySerializer {
     type Ok = ();
     type Error = BinarySerializeError;
 
@@ -306,7 +312,7 @@ impl<'a> serde::ser::SerializeTupleVariant for &'a mut BinarySerializer {
     }
 }
 
-impl<'a> serde::ser::SerializeMap for &'a mut BinarySerializer {
+impl serde::ser::SerializeMap for &mut BinarySerializer {
     type Ok = ();
     type Error = BinarySerializeError;
 
@@ -332,7 +338,7 @@ impl<'a> serde::ser::SerializeMap for &'a mut BinarySerializer {
     }
 }
 
-impl<'a> serde::ser::SerializeStruct for &'a mut BinarySerializer {
+impl serde::ser::SerializeStruct for &mut BinarySerializer {
     type Ok = ();
     type Error = BinarySerializeError;
 
@@ -349,7 +355,7 @@ impl<'a> serde::ser::SerializeStruct for &'a mut BinarySerializer {
     }
 }
 
-impl<'a> serde::ser::SerializeStructVariant for &'a mut BinarySerializer {
+impl serde::ser::SerializeStructVariant for &mut BinarySerializer {
     type Ok = ();
     type Error = BinarySerializeError;
 
@@ -452,7 +458,7 @@ impl<'de> BinaryDeserializer<'de> {
     }
 }
 
-impl<'de, 'a> serde::Deserializer<'de> for &'a mut BinaryDeserializer<'de> {
+impl<'de> serde::Deserializer<'de> for &mut BinaryDeserializer<'de> {
     type Error = BinarySerializeError;
 
     fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
@@ -0,0 +1,451 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    hash::{DefaultHasher, Hash, Hasher},
+    time::Duration,
+};
+
+use tracing::debug;
+
+use crate::{
+    error::IrsError,
+    msg::{Msg, header::DeviceCode},
+    transport::Transport,
+};
+
+/// Receiver address that [`Router`] forwards to every other link, unless
+/// changed with [`Router::with_broadcast`].
+pub const BROADCAST: DeviceCode = DeviceCode::Unknown(0xFF);
+
+/// How many recent broadcast frames are remembered to break loops.
+const SEEN_BROADCASTS: usize = 64;
+
+/// Handle of a link added with [`Router::add_link`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub struct LinkId(usize);
+
+impl LinkId {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// What to do with frames whose receiver has no route.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum UnknownReceiver {
+    #[default]
+    Drop,
+    /// Send them out this link, e.g. the uplink to the backend.
+    Forward(LinkId),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DropReason {
+    /// No route for the receiver and no fallback link.
+    UnknownReceiver,
+    /// The route leads back out of the link the frame came in on.
+    Loop,
+    /// The same broadcast frame was forwarded recently.
+    Duplicate,
+}
+
+/// Where a frame went.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Route {
+    Unicast(LinkId),
+    Broadcast(Vec<LinkId>),
+    Dropped(DropReason),
+}
+
+/// Sends that failed while forwarding a frame, with the route it took.
+#[derive(Debug)]
+pub struct ForwardError {
+    pub route: Route,
+    /// The links the frame could not be sent out of, and why.
+    pub failed: Vec<(LinkId, IrsError)>,
+}
+
+impl std::error::Error for ForwardError {}
+
+impl fmt::Display for ForwardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "forwarding failed on")?;
+        for (link, e) in &self.failed {
+            write!(f, " link {}: {e};", link.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Frame counters of a [`Router`].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct RouterStats {
+    pub unicast: u64,
+    pub broadcast: u64,
+    pub unknown_receiver: u64,
+    pub loops: u64,
+    pub duplicates: u64,
+}
+
+/// Forwards frames between several transports by their receiver address.
+///
+/// Routes map a receiver [`DeviceCode`] to a link, optionally narrowed to
+/// one `client_id` so that e.g. several apps behind different links can be
+/// told apart; a route for the frame's client ID wins over one for any
+/// client. Frames addressed to the broadcast receiver go out of every link
+/// but the one they came in on. A frame is never sent back out of its own
+/// link, and a broadcast frame already forwarded recently is dropped, so two
+/// routers wired to each other do not bounce frames forever. Frames whose
+/// receiver has no route, or that carry no receiver at all, are handled by
+/// the [`UnknownReceiver`] policy.
+pub struct Router<T> {
+    links: Vec<T>,
+    routes: BTreeMap<(DeviceCode, Option<u32>), LinkId>,
+    broadcast: DeviceCode,
+    unknown: UnknownReceiver,
+    seen: VecDeque<u64>,
+    stats: RouterStats,
+}
+
+impl<T> Default for Router<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Router<T> {
+    pub fn new() -> Self {
+        Router {
+            links: Vec::new(),
+            routes: BTreeMap::new(),
+            broadcast: BROADCAST,
+            unknown: UnknownReceiver::default(),
+            seen: VecDeque::new(),
+            stats: RouterStats::default(),
+        }
+    }
+    pub fn with_broadcast(mut self, receiver: DeviceCode) -> Self {
+        self.broadcast = receiver;
+        self
+    }
+    pub fn with_unknown(mut self, policy: UnknownReceiver) -> Self {
+        self.unknown = policy;
+        self
+    }
+
+    pub fn add_link(&mut self, transport: T) -> LinkId {
+        self.links.push(transport);
+        LinkId(self.links.len() - 1)
+    }
+    /// Send frames for `receiver` out of `link`, whatever their client ID.
+    pub fn route(&mut self, receiver: DeviceCode, link: LinkId) -> &mut Self {
+        self.routes.insert((receiver, None), link);
+        self
+    }
+    /// Send frames for `receiver` carrying `client_id` out of `link`.
+    pub fn route_client(
+        &mut self,
+        receiver: DeviceCode,
+        client_id: u32,
+        link: LinkId,
+    ) -> &mut Self {
+        self.routes.insert((receiver, Some(client_id)), link);
+        self
+    }
+
+    pub fn link(&self, id: LinkId) -> &T {
+        &self.links[id.0]
+    }
+    pub fn link_mut(&mut self, id: LinkId) -> &mut T {
+        &mut self.links[id.0]
+    }
+    pub fn stats(&self) -> RouterStats {
+        self.stats
+    }
+
+    /// Where `msg`, received on `from`, would go, without sending it.
+    pub fn resolve(&self, from: LinkId, msg: &Msg) -> Route {
+        let vh = msg.var_header();
+        let Some(receiver) = vh.receiver else {
+            return self.fallback(from);
+        };
+        if receiver == self.broadcast {
+            if self.seen.contains(&fingerprint(msg)) {
+                return Route::Dropped(DropReason::Duplicate);
+            }
+            let others = (0..self.links.len())
+                .map(LinkId)
+                .filter(|&id| id != from)
+                .collect();
+            return Route::Broadcast(others);
+        }
+        let route = vh
+            .client_id
+            .and_then(|client_id| self.routes.get(&(receiver, Some(client_id))))
+            .or_else(|| self.routes.get(&(receiver, None)));
+        match route {
+            Some(&to) if to == from => Route::Dropped(DropReason::Loop),
+            Some(&to) => Route::Unicast(to),
+            None => self.fallback(from),
+        }
+    }
+
+    fn fallback(&self, from: LinkId) -> Route {
+        match self.unknown {
+            UnknownReceiver::Forward(to) if to == from => Route::Dropped(DropReason::Loop),
+            UnknownReceiver::Forward(to) => Route::Unicast(to),
+            UnknownReceiver::Drop => Route::Dropped(DropReason::UnknownReceiver),
+        }
+    }
+}
+
+impl<T: Transport> Router<T> {
+    /// Forward `msg`, received on `from`, according to the routes. A
+    /// broadcast goes out of every other link even if sending on some of
+    /// them fails.
+    pub fn forward(&mut self, from: LinkId, msg: &Msg) -> Result<Route, ForwardError> {
+        let route = self.resolve(from, msg);
+        let mut failed = Vec::new();
+        match &route {
+            Route::Unicast(to) => match self.links[to.0].send(msg) {
+                Ok(()) => self.stats.unicast += 1,
+                Err(e) => failed.push((*to, e)),
+            },
+            Route::Broadcast(to) => {
+                if self.seen.len() == SEEN_BROADCASTS {
+                    self.seen.pop_front();
+                }
+                self.seen.push_back(fingerprint(msg));
+                for &id in to {
+                    if let Err(e) = self.links[id.0].send(msg) {
+                        failed.push((id, e));
+                    }
+                }
+                self.stats.broadcast += 1;
+            }
+            Route::Dropped(reason) => {
+                debug!(
+                    "dropping message {} from link {}: {reason:?}",
+                    msg.get_msg_id(),
+                    from.0
+                );
+                match reason {
+                    DropReason::UnknownReceiver => self.stats.unknown_receiver += 1,
+                    DropReason::Loop => self.stats.loops += 1,
+                    DropReason::Duplicate => self.stats.duplicates += 1,
+                }
+            }
+        }
+        if failed.is_empty() {
+            Ok(route)
+        } else {
+            Err(ForwardError { route, failed })
+        }
+    }
+
+    /// Wait at most `timeout` on each link in turn for one frame and forward
+    /// it. Returns the links that had a frame, with the route it took, and
+    /// the links that failed to receive or to send, with their error. A
+    /// failing link does not keep the others from being served.
+    pub fn poll(&mut self, timeout: Duration) -> Vec<(LinkId, Result<Route, IrsError>)> {
+        let mut polled = Vec::new();
+        for from in (0..self.links.len()).map(LinkId) {
+            let msg = match self.links[from.0].recv_timeout(timeout) {
+                Ok(msg) => msg,
+                Err(IrsError::Timeout) => continue,
+                Err(e) => {
+                    debug!("link {}: {e}", from.0);
+                    polled.push((from, Err(e)));
+                    continue;
+                }
+            };
+            match self.forward(from, &msg) {
+                Ok(route) => polled.push((from, Ok(route))),
+                Err(ForwardError { route, failed }) => {
+                    polled.push((from, Ok(route)));
+                    polled.extend(failed.into_iter().map(|(to, e)| (to, Err(e))));
+                }
+            }
+        }
+        polled
+    }
+}
+
+/// Identifies a frame by its encoded bytes.
+fn fingerprint(msg: &Msg) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    msg.to_bytes().unwrap_or_default().hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        msg::{
+            header::MsgType,
+            params::{Param, data::Param470},
+        },
+        transport::{Framed, memory},
+    };
+
+    type Link = Framed<memory::MemoryLink>;
+
+    /// A router over `n` links, with the far end of each link.
+    fn router(n: usize) -> (Router<Link>, Vec<Link>) {
+        let mut router = Router::new();
+        let mut peers = Vec::new();
+        for _ in 0..n {
+            let (near, far) = memory::pair();
+            router.add_link(near);
+            peers.push(far);
+        }
+        (router, peers)
+    }
+
+    fn msg(receiver: DeviceCode, client_id: u32) -> Msg {
+        let mut msg = Msg::new()
+            .with_sender(DeviceCode::MobileApp)
+            .with_receiver(receiver);
+        msg.set_client_id(client_id);
+        msg.add_param(Param::of(Param470));
+        msg
+    }
+
+    const SHORT: Duration = Duration::from_millis(20);
+
+    #[test]
+    fn test_unicast_by_receiver_and_client_id() {
+        let (mut router, mut peers) = router(3);
+        let [app, board, backend] = [LinkId(0), LinkId(1), LinkId(2)];
+        router
+            .route(DeviceCode::MowerMainBoardApplicationSw, board)
+            .route(DeviceCode::MobileApp, app)
+            .route_client(DeviceCode::MobileApp, 7, backend);
+
+        peers[0]
+            .send(&msg(DeviceCode::MowerMainBoardApplicationSw, 1))
+            .unwrap();
+        let polled = router.poll(SHORT);
+        assert!(
+            matches!(&polled[..], [(from, Ok(Route::Unicast(to)))] if *from == app && *to == board)
+        );
+        let got = peers[1].recv().unwrap();
+        assert_eq!(
+            got.receiver(),
+            Some(DeviceCode::MowerMainBoardApplicationSw)
+        );
+
+        let to_app = msg(DeviceCode::MobileApp, 7);
+        assert_eq!(
+            router.forward(board, &to_app).unwrap(),
+            Route::Unicast(backend)
+        );
+        let to_app = msg(DeviceCode::MobileApp, 8);
+        assert_eq!(router.forward(board, &to_app).unwrap(), Route::Unicast(app));
+        assert_eq!(router.stats().unicast, 3);
+    }
+
+    #[test]
+    fn test_broadcast_unknown_and_loops() {
+        let (mut router, mut peers) = router(3);
+        let [app, board, backend] = [LinkId(0), LinkId(1), LinkId(2)];
+        router.route(DeviceCode::MobileApp, app);
+
+        let hello = msg(BROADCAST, 1);
+        assert_eq!(
+            router.forward(app, &hello).unwrap(),
+            Route::Broadcast(vec![board, backend])
+        );
+        assert!(peers[1].recv_timeout(SHORT).is_ok());
+        assert!(peers[2].recv_timeout(SHORT).is_ok());
+        assert!(matches!(
+            peers[0].recv_timeout(SHORT),
+            Err(IrsError::Timeout)
+        ));
+        // the same frame coming back round is not forwarded again
+        assert_eq!(
+            router.forward(backend, &hello).unwrap(),
+            Route::Dropped(DropReason::Duplicate)
+        );
+
+        let to_app = msg(DeviceCode::MobileApp, 1);
+        assert_eq!(
+            router.forward(app, &to_app).unwrap(),
+            Route::Dropped(DropReason::Loop)
+        );
+        let stray = msg(DeviceCode::Unknown(0x21), 1);
+        assert_eq!(
+            router.forward(board, &stray).unwrap(),
+            Route::Dropped(DropReason::UnknownReceiver)
+        );
+        let mut router = router.with_unknown(UnknownReceiver::Forward(backend));
+        assert_eq!(
+            router.forward(board, &stray).unwrap(),
+            Route::Unicast(backend)
+        );
+        assert_eq!(
+            router.forward(backend, &stray).unwrap(),
+            Route::Dropped(DropReason::Loop)
+        );
+        assert_eq!(
+            router.stats(),
+            RouterStats {
+                unicast: 1,
+                broadcast: 1,
+                unknown_receiver: 1,
+                loops: 2,
+                duplicates: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_frames_without_receiver() {
+        let (router, _peers) = router(2);
+        let [app, board] = [LinkId(0), LinkId(1)];
+        let mut router = router.with_unknown(UnknownReceiver::Forward(board));
+        router.route(DeviceCode::MowerMainBoardApplicationSw, board);
+        // legacy connects carry no receiver, so they take the fallback and
+        // not the route of the default receiver
+        let mut connect = Msg::new();
+        connect.set_message_type(MsgType::Connect);
+        let connect = Msg::from_bytes(&connect.to_bytes().unwrap()).unwrap();
+        assert_eq!(connect.receiver(), None);
+        assert_eq!(router.resolve(app, &connect), Route::Unicast(board));
+        let router = router.with_unknown(UnknownReceiver::Drop);
+        assert_eq!(
+            router.resolve(app, &connect),
+            Route::Dropped(DropReason::UnknownReceiver)
+        );
+    }
+
+    #[test]
+    fn test_failing_link() {
+        let (mut router, peers) = router(3);
+        let [app, board, gone] = [LinkId(0), LinkId(1), LinkId(2)];
+        let Ok([mut app_end, mut board_end, gone_end]) = <[Link; 3]>::try_from(peers) else {
+            unreachable!()
+        };
+        router.route(DeviceCode::MowerMainBoardApplicationSw, board);
+        drop(gone_end);
+
+        // a broadcast still goes out of the links that work
+        let err = router.forward(app, &msg(BROADCAST, 1)).unwrap_err();
+        assert_eq!(err.route, Route::Broadcast(vec![board, gone]));
+        assert!(matches!(&err.failed[..], [(link, IrsError::Io(_))] if *link == gone));
+        assert!(board_end.recv_timeout(SHORT).is_ok());
+
+        // the closed link is reported without losing the frame routed before
+        app_end
+            .send(&msg(DeviceCode::MowerMainBoardApplicationSw, 1))
+            .unwrap();
+        let polled = router.poll(SHORT);
+        assert!(matches!(
+            &polled[..],
+            [(a, Ok(Route::Unicast(b))), (g, Err(IrsError::Closed))]
+                if *a == app && *b == board && *g == gone
+        ));
+        assert!(board_end.recv_timeout(SHORT).is_ok());
+    }
+}
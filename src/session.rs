@@ -0,0 +1,756 @@
+#[cfg(feature = "key-exchange")]
+pub mod key_exchange;
+pub(crate) mod machine;
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+pub use machine::Heartbeat;
+use machine::{Addressing, Connect, Due, Inbound, KeepAlive};
+use tracing::debug;
+
+#[cfg(feature = "key-exchange")]
+use crate::msg::params::Param;
+use crate::{
+    clock::{Clock, SystemClock},
+    error::IrsError,
+    msg::{
+        Msg,
+        cipher::{PayloadCipher, SessionCipher},
+        header::{DeviceCode, MsgType, VarHeader},
+    },
+    transport::Transport,
+};
+
+/// Which connect handshake a session uses.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum ConnectMode {
+    /// Connect / ConnectAck / DisConnect.
+    Legacy,
+    /// ConnectExtended / ConnectExtendedAck / DisConnectExtended.
+    #[default]
+    Extended,
+}
+
+impl ConnectMode {
+    pub(crate) fn connect_type(&self) -> MsgType {
+        match self {
+            ConnectMode::Legacy => MsgType::Connect,
+            ConnectMode::Extended => MsgType::ConnectExtended,
+        }
+    }
+    pub(crate) fn ack_type(&self) -> MsgType {
+        match self {
+            ConnectMode::Legacy => MsgType::ConnectAck,
+            ConnectMode::Extended => MsgType::ConnectExtendedAck,
+        }
+    }
+    pub(crate) fn disconnect_type(&self) -> MsgType {
+        match self {
+            ConnectMode::Legacy => MsgType::DisConnect,
+            ConnectMode::Extended => MsgType::DisConnectExtended,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SessionState {
+    /// No handshake yet, or the last one was refused.
+    Idle,
+    Connected,
+    /// Disconnected by either side; the session cannot be reused.
+    Disconnected,
+}
+
+/// Connection state machine on top of any [`Transport`].
+///
+/// The client side calls [`Session::connect`], the board side
+/// [`Session::accept`]. Data frames can only be exchanged once the handshake
+/// succeeded; they are stamped with the negotiated client ID, sender and
+/// receiver.
+///
+/// With a non-zero keep-alive interval (proposed by the client in its connect
+/// var header, adopted by the board) the session declares the peer dead once
+/// a whole interval passes without receiving anything, and, given a
+/// [`Heartbeat`], sends it after half the interval without sending anything.
+/// Both checks run in [`Session::tick`], which [`Session::recv`] calls while
+/// waiting.
+///
+/// The `connect_return_code` values are not confirmed against DGE-RLM-0069
+/// yet, so the session compares raw bytes: an ack carrying the accept code
+/// (by default the one [`VarHeader::new`] fills in) accepts the connect, any
+/// other code refuses it.
+///
+/// Once a cipher is set with [`Session::set_cipher`], the transport seals the
+/// payloads sent and drops received frames that do not open, see
+/// [`crate::msg::cipher`]. With [`Session::with_key_exchange`] the cipher is
+/// agreed on during an extended connect.
+pub struct Session<T> {
+    transport: T,
+    state: SessionState,
+    mode: ConnectMode,
+    addressing: Addressing,
+    accept_code: u8,
+    timeout: Duration,
+    keep_alive: KeepAlive,
+    heartbeat: Option<Heartbeat>,
+    clock: Box<dyn Clock>,
+    #[cfg(feature = "key-exchange")]
+    key_exchange: Option<key_exchange::KeyExchange>,
+}
+
+impl<T: Transport> Session<T> {
+    pub fn new(transport: T) -> Self {
+        let defaults = VarHeader::new();
+        let clock = Box::new(SystemClock);
+        let now = clock.now();
+        Session {
+            transport,
+            state: SessionState::Idle,
+            mode: ConnectMode::default(),
+            addressing: Addressing {
+                client_id: defaults.client_id.unwrap_or_default(),
+                sender: defaults.sender.unwrap_or(DeviceCode::Unknown(0)),
+                receiver: defaults.receiver.unwrap_or(DeviceCode::Unknown(0)),
+            },
+            accept_code: defaults.connect_return_code.unwrap_or_default(),
+            timeout: Duration::from_secs(1),
+            keep_alive: KeepAlive::new(
+                Duration::from_secs(defaults.keepalive().unwrap_or_default().into()),
+                now,
+            ),
+            heartbeat: None,
+            clock,
+            #[cfg(feature = "key-exchange")]
+            key_exchange: None,
+        }
+    }
+    pub fn with_mode(mut self, mode: ConnectMode) -> Self {
+        self.mode = mode;
+        self
+    }
+    pub fn with_client_id(mut self, v: u32) -> Self {
+        self.addressing.client_id = v;
+        self
+    }
+    pub fn with_sender(mut self, v: DeviceCode) -> Self {
+        self.addressing.sender = v;
+        self
+    }
+    pub fn with_receiver(mut self, v: DeviceCode) -> Self {
+        self.addressing.receiver = v;
+        self
+    }
+    /// `connect_return_code` that means the connect was accepted.
+    pub fn with_accept_code(mut self, code: u8) -> Self {
+        self.accept_code = code;
+        self
+    }
+    /// Keep-alive interval to propose on connect, whole seconds up to
+    /// `u16::MAX`; zero disables keep-alive.
+    pub fn with_keep_alive(mut self, interval: Duration) -> Self {
+        let interval = Duration::from_secs(interval.as_secs().min(u16::MAX.into()));
+        self.keep_alive.set_interval(interval);
+        self
+    }
+    /// Frame to send when idle and to consume on receipt, see [`Heartbeat`].
+    pub fn with_heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+        self.heartbeat = Some(heartbeat);
+        self
+    }
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self.keep_alive.reset(self.clock.now());
+        self
+    }
+    /// How long to wait for the handshake reply and in [`Session::recv`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Agree on a session key while connecting. Needs [`ConnectMode::Extended`];
+    /// a board refuses connects without a valid offer.
+    #[cfg(feature = "key-exchange")]
+    pub fn with_key_exchange(mut self, key_exchange: key_exchange::KeyExchange) -> Self {
+        self.key_exchange = Some(key_exchange);
+        self
+    }
+
+    /// Seal and open the frames of this session with `cipher` from now on,
+    /// e.g. with a key agreed on during connect. Sequence numbers start
+    /// over, so the key must not have been used before.
+    pub fn set_cipher(&mut self, cipher: impl PayloadCipher + 'static) {
+        let cipher = SessionCipher::new(cipher);
+        self.transport.set_cipher(Some(Arc::new(cipher)));
+    }
+    /// Stop sealing; received sealed sections are passed on as they are.
+    pub fn clear_cipher(&mut self) {
+        self.transport.set_cipher(None);
+    }
+
+    pub fn state(&self) -> SessionState {
+        self.state
+    }
+    pub fn mode(&self) -> ConnectMode {
+        self.mode
+    }
+    pub fn client_id(&self) -> u32 {
+        self.addressing.client_id
+    }
+    /// Our own device code.
+    pub fn sender(&self) -> DeviceCode {
+        self.addressing.sender
+    }
+    /// The peer's device code.
+    pub fn receiver(&self) -> DeviceCode {
+        self.addressing.receiver
+    }
+    /// Negotiated keep-alive interval, zero if disabled.
+    pub fn keep_alive(&self) -> Duration {
+        self.keep_alive.interval()
+    }
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Perform the client side of the handshake.
+    pub fn connect(&mut self) -> Result<(), IrsError> {
+        match self.state {
+            SessionState::Connected => return Ok(()),
+            SessionState::Disconnected => return Err(IrsError::Closed),
+            SessionState::Idle => {}
+        }
+        let (handshake, request) = Connect::start(
+            &self.addressing,
+            self.mode,
+            self.accept_code,
+            self.keep_alive.interval(),
+            #[cfg(feature = "key-exchange")]
+            self.key_exchange.as_ref(),
+        )?;
+        self.send_frame(&request)?;
+        let ack = self.recv_frame()?;
+        if let Some(cipher) = handshake.finish(&ack, &mut self.addressing)? {
+            self.transport.set_cipher(Some(Arc::new(cipher)));
+        }
+        self.state = SessionState::Connected;
+        Ok(())
+    }
+
+    /// Perform the board side of the handshake, accepting any client.
+    pub fn accept(&mut self) -> Result<(), IrsError> {
+        let code = self.accept_code;
+        self.accept_with(|_| code)
+    }
+
+    /// Perform the board side of the handshake; `decide` inspects the
+    /// client's connect var header and picks the return code to answer with.
+    /// Anything but the accept code refuses the connect.
+    pub fn accept_with<F>(&mut self, decide: F) -> Result<(), IrsError>
+    where
+        F: FnOnce(&VarHeader) -> u8,
+    {
+        if self.state != SessionState::Idle {
+            return Err(IrsError::UnexpectedMsg(MsgType::Connect));
+        }
+        let request = self.recv_frame()?;
+        self.mode = match request.get_message_type() {
+            MsgType::Connect => ConnectMode::Legacy,
+            MsgType::ConnectExtended => ConnectMode::Extended,
+            other => return Err(IrsError::UnexpectedMsg(other)),
+        };
+        let vh = request.var_header();
+        if let Some(client_id) = vh.client_id {
+            self.addressing.client_id = client_id;
+        }
+        if let Some(secs) = vh.keepalive() {
+            self.keep_alive
+                .set_interval(Duration::from_secs(secs.into()));
+        }
+        if let Some(sender) = vh.sender {
+            self.addressing.receiver = sender;
+        }
+        if let (ConnectMode::Extended, Some(receiver)) = (self.mode, vh.receiver) {
+            self.addressing.sender = receiver;
+        }
+        let code = decide(vh);
+        let mut ack = self.frame(self.mode.ack_type());
+        #[cfg(feature = "key-exchange")]
+        let (code, cipher) = self.answer_key_exchange(&request, code, &mut ack);
+        ack.var_header_mut().connect_return_code = Some(code);
+        self.send_frame(&ack)?;
+        if code != self.accept_code {
+            return Err(IrsError::ConnectRefused(code));
+        }
+        #[cfg(feature = "key-exchange")]
+        if let Some(cipher) = cipher {
+            self.transport.set_cipher(Some(Arc::new(cipher)));
+        }
+        self.state = SessionState::Connected;
+        Ok(())
+    }
+
+    /// Send `msg` as a Data frame of this session.
+    pub fn send(&mut self, mut msg: Msg) -> Result<(), IrsError> {
+        self.ensure_connected()?;
+        msg.set_message_type(MsgType::Data);
+        self.addressing.stamp(&mut msg);
+        self.send_frame(&msg)
+    }
+
+    /// Next Data frame from the peer, waiting at most the session timeout.
+    pub fn recv(&mut self) -> Result<Msg, IrsError> {
+        self.recv_timeout(self.timeout)
+    }
+
+    /// Next Data frame from the peer. A disconnect from the peer ends the
+    /// session and is reported as [`IrsError::Closed`]; frames matching the
+    /// session's [`Heartbeat`] are consumed here and never returned.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Msg, IrsError> {
+        self.ensure_connected()?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.tick()?;
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(IrsError::Timeout);
+            }
+            let mut wait = deadline - now;
+            if let Some(every) = self.keep_alive.check_every() {
+                wait = wait.min(every);
+            }
+            let msg = match self.transport.recv_timeout(wait) {
+                Err(IrsError::Timeout) => continue,
+                Err(IrsError::Closed) => {
+                    self.close();
+                    return Err(IrsError::Closed);
+                }
+                other => other?,
+            };
+            self.keep_alive.received(self.clock.now());
+            match self.addressing.classify(msg, self.heartbeat.as_ref()) {
+                Inbound::Data(msg) => return Ok(msg),
+                Inbound::Heartbeat | Inbound::Foreign => {}
+                Inbound::Disconnect => {
+                    self.close();
+                    return Err(IrsError::Closed);
+                }
+                Inbound::Unexpected(other) => return Err(IrsError::UnexpectedMsg(other)),
+            }
+        }
+    }
+
+    /// Run the keep-alive checks: send the heartbeat, if any, if we have been
+    /// quiet for half the interval, and fail with [`IrsError::KeepAliveExpired`] (ending
+    /// the session) if the peer has been quiet for the whole interval.
+    pub fn tick(&mut self) -> Result<(), IrsError> {
+        if self.state != SessionState::Connected {
+            return Ok(());
+        }
+        match self.keep_alive.due(self.clock.now()) {
+            Due::Nothing => Ok(()),
+            Due::Heartbeat => match &self.heartbeat {
+                Some(heartbeat) => {
+                    let frame = heartbeat.frame(&self.addressing);
+                    self.send_frame(&frame)
+                }
+                None => Ok(()),
+            },
+            Due::Expired => {
+                debug!("keep-alive expired");
+                self.close();
+                Err(IrsError::KeepAliveExpired)
+            }
+        }
+    }
+
+    /// Orderly disconnect; a no-op unless connected.
+    pub fn disconnect(&mut self) -> Result<(), IrsError> {
+        if self.state != SessionState::Connected {
+            return Ok(());
+        }
+        let msg = self.frame(self.mode.disconnect_type());
+        let sent = self.send_frame(&msg);
+        self.close();
+        sent
+    }
+
+    /// End the session, leaving the transport without a cipher.
+    fn close(&mut self) {
+        self.state = SessionState::Disconnected;
+        self.clear_cipher();
+    }
+
+    fn ensure_connected(&self) -> Result<(), IrsError> {
+        match self.state {
+            SessionState::Connected => Ok(()),
+            SessionState::Idle => Err(IrsError::NotConnected),
+            SessionState::Disconnected => Err(IrsError::Closed),
+        }
+    }
+
+    /// Board side of the key exchange: add our reply to `ack`, or turn an
+    /// accepting `code` into [`key_exchange::REFUSED`].
+    #[cfg(feature = "key-exchange")]
+    fn answer_key_exchange(
+        &self,
+        request: &Msg,
+        code: u8,
+        ack: &mut Msg,
+    ) -> (u8, Option<SessionCipher>) {
+        let accepted = code == self.accept_code;
+        let Some(kx) = self.key_exchange.as_ref().filter(|_| accepted) else {
+            return (code, None);
+        };
+        if self.mode != ConnectMode::Extended {
+            debug!("refusing key exchange: legacy connect");
+            return (key_exchange::REFUSED, None);
+        }
+        let binding = key_exchange::Binding {
+            client_id: self.addressing.client_id,
+            client: self.addressing.receiver,
+            board: self.addressing.sender,
+        };
+        match kx.respond(request, &binding) {
+            Ok((reply, cipher)) => {
+                ack.add_param(Param::of(reply));
+                (code, Some(cipher))
+            }
+            Err(reason) => {
+                debug!("refusing key exchange: {reason}");
+                (key_exchange::REFUSED, None)
+            }
+        }
+    }
+
+    fn recv_frame(&mut self) -> Result<Msg, IrsError> {
+        let msg = self.transport.recv_timeout(self.timeout)?;
+        self.keep_alive.received(self.clock.now());
+        Ok(msg)
+    }
+
+    fn send_frame(&mut self, msg: &Msg) -> Result<(), IrsError> {
+        self.transport.send(msg)?;
+        self.keep_alive.sent(self.clock.now());
+        Ok(())
+    }
+
+    fn frame(&self, msg_type: MsgType) -> Msg {
+        self.addressing.frame(msg_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{
+        clock::ManualClock,
+        msg::params::{Param, data},
+        transport::{Framed, memory},
+    };
+
+    fn request(msg_id: u8) -> Msg {
+        let mut msg = Msg::new();
+        msg.set_msg_id(msg_id);
+        msg.add_param(Param::of(data::Param470));
+        msg
+    }
+
+    #[test]
+    fn test_legacy_handshake_data_disconnect() {
+        let (a, b) = memory::pair();
+        let mut client = Session::new(a)
+            .with_mode(ConnectMode::Legacy)
+            .with_client_id(7)
+            .with_sender(DeviceCode::PcConnectedToMainBoardUartInterface)
+            .with_receiver(DeviceCode::MowerMainBoardApplicationSw);
+        let mut board = Session::new(b)
+            .with_sender(DeviceCode::Unknown(0))
+            .with_receiver(DeviceCode::Unknown(0));
+        thread::scope(|s| {
+            s.spawn(|| {
+                board.accept().unwrap();
+                let msg = board.recv().unwrap();
+                assert_eq!(msg.get_msg_id(), 1);
+                board.send(request(2)).unwrap();
+                assert!(matches!(board.recv(), Err(IrsError::Closed)));
+            });
+            client.connect().unwrap();
+            client.send(request(1)).unwrap();
+            let reply = client.recv().unwrap();
+            assert_eq!(reply.get_msg_id(), 2);
+            assert_eq!(reply.var_header().client_id, Some(7));
+            client.disconnect().unwrap();
+        });
+        assert_eq!(board.mode(), ConnectMode::Legacy);
+        assert_eq!(board.client_id(), 7);
+        assert_eq!(
+            board.receiver(),
+            DeviceCode::PcConnectedToMainBoardUartInterface
+        );
+        assert_eq!(board.state(), SessionState::Disconnected);
+        assert_eq!(client.state(), SessionState::Disconnected);
+    }
+
+    #[test]
+    fn test_extended_handshake_adopts_client_id() {
+        let (a, mut board) = memory::pair();
+        let mut client = Session::new(a).with_client_id(1);
+        thread::scope(|s| {
+            s.spawn(|| {
+                let connect = board.recv().unwrap();
+                assert_eq!(connect.get_message_type(), MsgType::ConnectExtended);
+                let mut ack = Msg::new();
+                ack.set_message_type(MsgType::ConnectExtendedAck);
+                let vh = ack.var_header_mut();
+                vh.connect_return_code = VarHeader::new().connect_return_code;
+                vh.client_id = Some(0x0102_0304);
+                board.send(&ack).unwrap();
+            });
+            client.connect().unwrap();
+        });
+        assert_eq!(client.state(), SessionState::Connected);
+        assert_eq!(client.client_id(), 0x0102_0304);
+    }
+
+    #[test]
+    fn test_data_refused_before_ack() {
+        let (a, _b) = memory::pair();
+        let mut client = Session::new(a);
+        assert!(matches!(
+            client.send(request(1)),
+            Err(IrsError::NotConnected)
+        ));
+        assert!(matches!(client.recv(), Err(IrsError::NotConnected)));
+    }
+
+    #[test]
+    fn test_connect_refused() {
+        let (a, b) = memory::pair();
+        let mut client = Session::new(a);
+        let mut board = Session::new(b);
+        thread::scope(|s| {
+            s.spawn(|| {
+                let res = board.accept_with(|_| 0x42);
+                assert!(matches!(res, Err(IrsError::ConnectRefused(0x42))));
+            });
+            assert!(matches!(
+                client.connect(),
+                Err(IrsError::ConnectRefused(0x42))
+            ));
+        });
+        assert_eq!(client.state(), SessionState::Idle);
+        assert_eq!(board.state(), SessionState::Idle);
+    }
+
+    #[test]
+    fn test_board_rejects_data_before_connect() {
+        let (mut a, b) = memory::pair();
+        let mut board = Session::new(b);
+        let mut data = request(1);
+        data.set_message_type(MsgType::Data);
+        a.send(&data).unwrap();
+        assert!(matches!(
+            board.accept(),
+            Err(IrsError::UnexpectedMsg(MsgType::Data))
+        ));
+    }
+
+    fn connected_pair(
+        keep_alive: Duration,
+        heartbeat: Option<Heartbeat>,
+        clock: &ManualClock,
+    ) -> (
+        Session<Framed<memory::MemoryLink>>,
+        Session<Framed<memory::MemoryLink>>,
+    ) {
+        let (a, b) = memory::pair();
+        let mut client = Session::new(a)
+            .with_keep_alive(keep_alive)
+            .with_clock(clock.clone())
+            .with_timeout(Duration::from_millis(20));
+        let mut board = Session::new(b)
+            .with_clock(clock.clone())
+            .with_timeout(Duration::from_millis(20));
+        if let Some(heartbeat) = heartbeat {
+            client = client.with_heartbeat(heartbeat.clone());
+            board = board.with_heartbeat(heartbeat);
+        }
+        thread::scope(|s| {
+            s.spawn(|| board.accept().unwrap());
+            client.connect().unwrap();
+        });
+        (client, board)
+    }
+
+    #[test]
+    fn test_keep_alive_negotiated() {
+        let clock = ManualClock::new();
+        let (client, board) = connected_pair(Duration::from_secs(10), None, &clock);
+        assert_eq!(client.keep_alive(), Duration::from_secs(10));
+        assert_eq!(board.keep_alive(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_heartbeat_keeps_peer_alive() {
+        let clock = ManualClock::new();
+        let heartbeat = Heartbeat::new(0x7fff, []);
+        let (mut client, mut board) =
+            connected_pair(Duration::from_secs(10), Some(heartbeat), &clock);
+        for _ in 0..4 {
+            clock.advance(Duration::from_secs(6));
+            // both sides have been quiet for more than half the interval, so
+            // each sends a heartbeat and consumes the other's without
+            // surfacing it
+            client.tick().unwrap();
+            assert!(matches!(board.recv(), Err(IrsError::Timeout)));
+            assert!(matches!(client.recv(), Err(IrsError::Timeout)));
+            assert_eq!(board.state(), SessionState::Connected);
+            assert_eq!(client.state(), SessionState::Connected);
+        }
+    }
+
+    #[test]
+    fn test_no_heartbeat_by_default() {
+        let clock = ManualClock::new();
+        let (mut client, mut board) = connected_pair(Duration::from_secs(10), None, &clock);
+        clock.advance(Duration::from_secs(6));
+        client.tick().unwrap();
+        assert!(matches!(board.recv(), Err(IrsError::Timeout)));
+        // an empty Data frame is passed on like any other
+        client.send(Msg::new()).unwrap();
+        assert!(board.recv().unwrap().payload().get_params().is_empty());
+    }
+
+    #[test]
+    fn test_silent_peer_declared_dead() {
+        let clock = ManualClock::new();
+        let (mut client, mut board) = connected_pair(Duration::from_secs(10), None, &clock);
+        clock.advance(Duration::from_secs(9));
+        board.tick().unwrap();
+        clock.advance(Duration::from_secs(1));
+        assert!(matches!(board.tick(), Err(IrsError::KeepAliveExpired)));
+        assert_eq!(board.state(), SessionState::Disconnected);
+        assert!(matches!(client.tick(), Err(IrsError::KeepAliveExpired)));
+    }
+
+    #[test]
+    fn test_keep_alive_disabled() {
+        let clock = ManualClock::new();
+        let (mut client, mut board) = connected_pair(Duration::ZERO, None, &clock);
+        clock.advance(Duration::from_secs(3600));
+        client.tick().unwrap();
+        board.tick().unwrap();
+        assert!(matches!(board.recv(), Err(IrsError::Timeout)));
+    }
+
+    #[cfg(feature = "aes")]
+    #[test]
+    fn test_encrypted_data() {
+        use std::io::Write;
+
+        use crate::msg::{
+            cipher::{Aes128Gcm, SEQ_SIZE, TAG_SIZE},
+            header::Header,
+            payload::Payload,
+        };
+
+        let clock = ManualClock::new();
+        let (mut client, mut board) = connected_pair(Duration::ZERO, None, &clock);
+        // keep a handle on the client's cipher to seal frames by hand
+        let cipher = Arc::new(SessionCipher::new(Aes128Gcm::new([7; 16])));
+        client.transport_mut().set_cipher(Some(cipher.clone()));
+        board.set_cipher(Aes128Gcm::new([7; 16]));
+        for msg_id in 1..=2 {
+            client.send(request(msg_id)).unwrap();
+            let msg = board.recv().unwrap();
+            assert_eq!(msg.get_msg_id(), msg_id);
+            assert!(msg.get_param(0).unwrap().get::<data::Param470>().is_some());
+        }
+        board.send(request(3)).unwrap();
+        let reply = client.recv().unwrap();
+        assert!(
+            reply
+                .get_param(0)
+                .unwrap()
+                .get::<data::Param470>()
+                .is_some()
+        );
+
+        // a frame lost on the way does not keep the next one from opening
+        let mut lost = client.frame(MsgType::Data);
+        lost.set_msg_id(4);
+        lost.to_bytes_sealed(&cipher).unwrap();
+        client.send(request(5)).unwrap();
+        assert_eq!(board.recv().unwrap().get_msg_id(), 5);
+
+        // one altered on the way is dropped, even with its CRC fixed up
+        let mut forged = client.frame(MsgType::Data);
+        forged.set_msg_id(6);
+        forged.add_param(Param::of(data::Param470));
+        let mut bytes = forged.to_bytes_sealed(&cipher).unwrap();
+        let crc_at = bytes.len() - 2;
+        bytes[crc_at - 1] ^= 0x01;
+        let payload_at = Header::SIZE + VarHeader::default_size(MsgType::Data).unwrap() as usize;
+        let crc = Payload::crc_over(&bytes[payload_at..]);
+        bytes[crc_at..].copy_from_slice(&crc.to_le_bytes());
+        client.transport_mut().get_mut().write_all(&bytes).unwrap();
+        client.send(request(7)).unwrap();
+        assert_eq!(board.recv().unwrap().get_msg_id(), 7);
+        assert_eq!(board.transport_mut().decoder().bad_frames(), 1);
+
+        // without the key the parameters stay sealed
+        client.send(request(8)).unwrap();
+        board.clear_cipher();
+        let msg = board.recv().unwrap();
+        assert!(msg.payload().get_params().is_empty());
+        assert_eq!(msg.payload().unencrypted_length, 0);
+        assert_eq!(msg.payload().encrypted.len(), SEQ_SIZE + 4 + TAG_SIZE);
+    }
+
+    #[cfg(feature = "key-exchange")]
+    #[test]
+    fn test_key_exchange_on_connect() {
+        use key_exchange::KeyExchange;
+
+        let (a, b) = memory::pair();
+        let mut client = Session::new(a)
+            .with_client_id(3)
+            .with_key_exchange(KeyExchange::x25519().with_psk(*b"rig"));
+        let mut board = Session::new(b).with_key_exchange(KeyExchange::x25519().with_psk(*b"rig"));
+        thread::scope(|s| {
+            s.spawn(|| {
+                board.accept().unwrap();
+                let msg = board.recv().unwrap();
+                assert!(msg.get_param(0).unwrap().get::<data::Param470>().is_some());
+            });
+            client.connect().unwrap();
+            client.send(request(1)).unwrap();
+        });
+
+        // a board expecting a key exchange refuses a client without one
+        let (a, b) = memory::pair();
+        let mut client = Session::new(a);
+        let mut board = Session::new(b).with_key_exchange(KeyExchange::pre_shared(*b"rig"));
+        thread::scope(|s| {
+            s.spawn(|| {
+                assert!(matches!(
+                    board.accept(),
+                    Err(IrsError::ConnectRefused(key_exchange::REFUSED))
+                ));
+            });
+            assert!(matches!(
+                client.connect(),
+                Err(IrsError::ConnectRefused(key_exchange::REFUSED))
+            ));
+        });
+    }
+}
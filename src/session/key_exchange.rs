@@ -0,0 +1,511 @@
+//! Session key agreement riding on ConnectExtended / ConnectExtendedAck.
+//!
+//! The client puts a [`KeyExchangeParam`] with a fresh nonce (and, for
+//! [`KeyExchangeMode::X25519`], an ephemeral public key) into the payload of
+//! its ConnectExtended; the board answers with its own nonce, public key and
+//! a confirmation tag in the ConnectExtendedAck. Both then derive two
+//! AES-128 session keys, one per direction, with HKDF-SHA256 from the
+//! pre-shared key and/or the X25519 shared secret, salted with both nonces
+//! and bound to the client ID, the client's device code and the board's,
+//! which must differ. The tag proves to the client that the board derived
+//! the same keys; the board refuses a connect whose nonce it has seen
+//! before.
+//!
+//! The parameter is this crate's own, not one from DGE-RLM-0069, so both
+//! ends must use this module.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::{
+    error::IrsError,
+    msg::{
+        Msg,
+        cipher::{Aes128Gcm, SessionCipher},
+        header::DeviceCode,
+        params::{IrsParam, ParamInfo},
+        serialization::BinarySerializeError,
+    },
+};
+
+/// `connect_return_code` a board refuses a connect with when the key
+/// exchange fails. Like the parameter it is this crate's own.
+pub const REFUSED: u8 = 0xFF;
+
+/// How many client nonces a board remembers to refuse replayed connects.
+const SEEN_NONCES: usize = 1024;
+const NONCE_SIZE: usize = 16;
+const TAG_SIZE: usize = 16;
+const CLIENT_TO_BOARD: &[u8] = b"irs-rs client to board key";
+const BOARD_TO_CLIENT: &[u8] = b"irs-rs board to client key";
+const CONFIRM: &[u8] = b"irs-rs key confirmation";
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum KeyExchangeMode {
+    /// Key derived from a secret configured on both ends, e.g. on test rigs.
+    PreShared,
+    /// Ephemeral X25519 agreement, optionally mixed with a pre-shared key.
+    X25519,
+}
+
+impl KeyExchangeMode {
+    fn to_u8(self) -> u8 {
+        match self {
+            KeyExchangeMode::PreShared => 1,
+            KeyExchangeMode::X25519 => 2,
+        }
+    }
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(KeyExchangeMode::PreShared),
+            2 => Some(KeyExchangeMode::X25519),
+            _ => None,
+        }
+    }
+}
+
+/// Key exchange data carried in the connect and ack payloads.
+///
+/// ```text
+///    |--------|----------|-----------------------|--------------------|
+///    | Mode   | Nonce    | Public key            | Confirmation tag   |
+///    | 8 bits | 16 bytes | 32 bytes, X25519 only | 16 bytes, ack only |
+///    |--------+----------+-----------------------+--------------------|
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyExchangeParam {
+    pub mode: KeyExchangeMode,
+    pub nonce: [u8; NONCE_SIZE],
+    pub public: Option<[u8; 32]>,
+    pub confirm: Option<[u8; TAG_SIZE]>,
+}
+
+impl IrsParam for KeyExchangeParam {
+    const ID: u16 = 0x7F00;
+    const NAME: &'static str = "KeyExchange";
+    const RESPONSE_TO: Option<u16> = None;
+
+    fn encode(&self) -> Result<Vec<u8>, BinarySerializeError> {
+        let mut buf = vec![self.mode.to_u8()];
+        buf.extend_from_slice(&self.nonce);
+        if let Some(public) = &self.public {
+            buf.extend_from_slice(public);
+        }
+        if let Some(confirm) = &self.confirm {
+            buf.extend_from_slice(confirm);
+        }
+        Ok(buf)
+    }
+    fn decode(bytes: &[u8]) -> Result<Self, BinarySerializeError> {
+        let invalid = || serde::de::Error::custom("invalid key exchange data");
+        let (&mode, rest) = bytes.split_first().ok_or_else(invalid)?;
+        let mode = KeyExchangeMode::from_u8(mode).ok_or_else(invalid)?;
+        let (nonce, rest) = rest.split_first_chunk().ok_or_else(invalid)?;
+        let (public, rest) = match mode {
+            KeyExchangeMode::X25519 => {
+                let (public, rest) = rest.split_first_chunk().ok_or_else(invalid)?;
+                (Some(*public), rest)
+            }
+            KeyExchangeMode::PreShared => (None, rest),
+        };
+        let confirm = match rest.len() {
+            0 => None,
+            TAG_SIZE => Some(rest.try_into().unwrap()),
+            _ => return Err(invalid()),
+        };
+        Ok(KeyExchangeParam {
+            mode,
+            nonce: *nonce,
+            public,
+            confirm,
+        })
+    }
+}
+
+inventory::submit! {
+    ParamInfo::of::<KeyExchangeParam>()
+}
+
+/// What the derived keys are bound to, the same on both ends.
+pub(crate) struct Binding {
+    pub client_id: u32,
+    pub client: DeviceCode,
+    pub board: DeviceCode,
+}
+
+impl Binding {
+    fn check(&self) -> Result<(), &'static str> {
+        if self.client == self.board {
+            return Err("client and board are the same device");
+        }
+        Ok(())
+    }
+}
+
+/// Key exchange settings of a session, see [`Session::with_key_exchange`].
+///
+/// Clones share the record of seen client nonces, so a board handing one
+/// clone to each accepted session refuses replays across all of them.
+///
+/// [`Session::with_key_exchange`]: crate::session::Session::with_key_exchange
+#[derive(Clone)]
+pub struct KeyExchange {
+    mode: KeyExchangeMode,
+    psk: Option<Vec<u8>>,
+    seen: Arc<Mutex<VecDeque<[u8; NONCE_SIZE]>>>,
+}
+
+impl std::fmt::Debug for KeyExchange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyExchange")
+            .field("mode", &self.mode)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyExchange {
+    pub fn pre_shared(psk: impl Into<Vec<u8>>) -> Self {
+        Self::new(KeyExchangeMode::PreShared, Some(psk.into()))
+    }
+    pub fn x25519() -> Self {
+        Self::new(KeyExchangeMode::X25519, None)
+    }
+    /// Mix `psk` into the X25519 agreement, authenticating the peer.
+    pub fn with_psk(mut self, psk: impl Into<Vec<u8>>) -> Self {
+        self.psk = Some(psk.into());
+        self
+    }
+    pub fn mode(&self) -> KeyExchangeMode {
+        self.mode
+    }
+
+    fn new(mode: KeyExchangeMode, psk: Option<Vec<u8>>) -> Self {
+        KeyExchange {
+            mode,
+            psk,
+            seen: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Client side: the parameter to send with ConnectExtended.
+    pub(crate) fn offer(&self) -> Result<Offer, IrsError> {
+        let (secret, public) = self.ephemeral()?;
+        Ok(Offer {
+            param: KeyExchangeParam {
+                mode: self.mode,
+                nonce: random()?,
+                public,
+                confirm: None,
+            },
+            secret,
+            psk: self.psk.clone(),
+        })
+    }
+
+    /// Board side: answer the client's offer in `request`, returning the
+    /// parameter for the ack and the session cipher, or why the connect is
+    /// refused.
+    pub(crate) fn respond(
+        &self,
+        request: &Msg,
+        binding: &Binding,
+    ) -> Result<(KeyExchangeParam, SessionCipher), &'static str> {
+        binding.check()?;
+        let offer = param_in(request).ok_or("no offer")?;
+        if offer.mode != self.mode {
+            return Err("mode mismatch");
+        }
+        {
+            let mut seen = self.seen.lock().unwrap();
+            if seen.contains(&offer.nonce) {
+                return Err("replayed nonce");
+            }
+            if seen.len() == SEEN_NONCES {
+                seen.pop_front();
+            }
+            seen.push_back(offer.nonce);
+        }
+        let unavailable = |_| "no randomness";
+        let (secret, public) = self.ephemeral().map_err(unavailable)?;
+        let nonce = random().map_err(unavailable)?;
+        let ikm =
+            ikm(secret.as_ref(), offer.public, self.psk.as_deref()).ok_or("weak public key")?;
+        let keys = derive(&ikm, &offer.nonce, &nonce, binding);
+        let reply = KeyExchangeParam {
+            mode: self.mode,
+            nonce,
+            public,
+            confirm: Some(keys.confirm),
+        };
+        let cipher = SessionCipher::directional(
+            Aes128Gcm::new(keys.board_to_client),
+            Aes128Gcm::new(keys.client_to_board),
+        );
+        Ok((reply, cipher))
+    }
+
+    fn ephemeral(&self) -> Result<(Option<StaticSecret>, Option<[u8; 32]>), IrsError> {
+        if self.mode != KeyExchangeMode::X25519 {
+            return Ok((None, None));
+        }
+        let secret = StaticSecret::from(random::<32>()?);
+        let public = PublicKey::from(&secret).to_bytes();
+        Ok((Some(secret), Some(public)))
+    }
+}
+
+/// A client's offer, kept until the ack arrives.
+pub(crate) struct Offer {
+    pub param: KeyExchangeParam,
+    secret: Option<StaticSecret>,
+    psk: Option<Vec<u8>>,
+}
+
+impl Offer {
+    /// Check the board's reply in `ack` and derive the session cipher.
+    pub(crate) fn finish(self, ack: &Msg, binding: &Binding) -> Result<SessionCipher, IrsError> {
+        binding.check().map_err(IrsError::KeyExchange)?;
+        let reply = param_in(ack).ok_or(IrsError::MissingParam(KeyExchangeParam::ID))?;
+        if reply.mode != self.param.mode {
+            return Err(IrsError::KeyExchange("mode mismatch"));
+        }
+        let ikm = ikm(self.secret.as_ref(), reply.public, self.psk.as_deref())
+            .ok_or(IrsError::KeyExchange("weak public key"))?;
+        let keys = derive(&ikm, &self.param.nonce, &reply.nonce, binding);
+        let matches = reply.confirm.is_some_and(|tag| {
+            tag.iter()
+                .zip(&keys.confirm)
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+        });
+        if !matches {
+            return Err(IrsError::KeyExchange("confirmation tag mismatch"));
+        }
+        Ok(SessionCipher::directional(
+            Aes128Gcm::new(keys.client_to_board),
+            Aes128Gcm::new(keys.board_to_client),
+        ))
+    }
+}
+
+/// The key exchange parameter of `msg`, if it carries a valid one.
+fn param_in(msg: &Msg) -> Option<KeyExchangeParam> {
+    let param = msg
+        .payload()
+        .get_params()
+        .iter()
+        .find(|p| p.id == KeyExchangeParam::ID)?;
+    match param.get::<KeyExchangeParam>() {
+        Some(data) => Some(data.clone()),
+        // decoded with a registry that does not know it
+        None => KeyExchangeParam::decode(param.data.as_raw()?).ok(),
+    }
+}
+
+/// Input key material: the X25519 shared secret if there is one, then the
+/// pre-shared key. None if the peer's public key is missing or of low order.
+fn ikm(
+    secret: Option<&StaticSecret>,
+    peer: Option<[u8; 32]>,
+    psk: Option<&[u8]>,
+) -> Option<Vec<u8>> {
+    let mut ikm = Vec::new();
+    if let Some(secret) = secret {
+        let shared = secret.diffie_hellman(&PublicKey::from(peer?));
+        if !shared.was_contributory() {
+            return None;
+        }
+        ikm.extend_from_slice(shared.as_bytes());
+    }
+    ikm.extend_from_slice(psk.unwrap_or_default());
+    Some(ikm)
+}
+
+/// What both ends derive from the agreement.
+struct Keys {
+    client_to_board: [u8; 16],
+    board_to_client: [u8; 16],
+    confirm: [u8; TAG_SIZE],
+}
+
+/// Session keys and confirmation tag, each expanded with its own label.
+fn derive(
+    ikm: &[u8],
+    client_nonce: &[u8; NONCE_SIZE],
+    board_nonce: &[u8; NONCE_SIZE],
+    binding: &Binding,
+) -> Keys {
+    let mut salt = [0; 2 * NONCE_SIZE];
+    salt[..NONCE_SIZE].copy_from_slice(client_nonce);
+    salt[NONCE_SIZE..].copy_from_slice(board_nonce);
+    let hkdf = Hkdf::<Sha256>::new(Some(&salt), ikm);
+    let expand = |label: &[u8]| {
+        let mut info = label.to_vec();
+        info.extend_from_slice(&binding.client_id.to_le_bytes());
+        info.push(binding.client.into());
+        info.push(binding.board.into());
+        let mut okm = [0; 16];
+        hkdf.expand(&info, &mut okm)
+            .expect("16 bytes is a valid HKDF-SHA256 output length");
+        okm
+    };
+    Keys {
+        client_to_board: expand(CLIENT_TO_BOARD),
+        board_to_client: expand(BOARD_TO_CLIENT),
+        confirm: expand(CONFIRM),
+    }
+}
+
+fn random<const N: usize>() -> Result<[u8; N], IrsError> {
+    let mut bytes = [0; N];
+    getrandom::getrandom(&mut bytes).map_err(std::io::Error::from)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::params::{Param, ParamRegistry, data};
+
+    fn binding() -> Binding {
+        Binding {
+            client_id: 7,
+            client: DeviceCode::PcConnectedToMainBoardUartInterface,
+            board: DeviceCode::MowerMainBoardApplicationSw,
+        }
+    }
+
+    fn carrying(param: KeyExchangeParam) -> Msg {
+        let mut msg = Msg::new();
+        msg.add_param(Param::of(param));
+        msg
+    }
+
+    /// The client's and the board's session cipher.
+    fn exchange(
+        client: &KeyExchange,
+        board: &KeyExchange,
+    ) -> Result<(SessionCipher, SessionCipher), IrsError> {
+        let offer = client.offer()?;
+        let request = carrying(offer.param.clone());
+        let (reply, board_cipher) = board
+            .respond(&request, &binding())
+            .map_err(|_| IrsError::ConnectRefused(REFUSED))?;
+        let client_cipher = offer.finish(&carrying(reply), &binding())?;
+        Ok((client_cipher, board_cipher))
+    }
+
+    #[test]
+    fn test_param_round_trip() {
+        let param = KeyExchangeParam {
+            mode: KeyExchangeMode::X25519,
+            nonce: [1; 16],
+            public: Some([2; 32]),
+            confirm: Some([3; 16]),
+        };
+        let bytes = Param::of(param.clone()).to_bytes().unwrap();
+        assert_eq!(bytes.len(), 4 + 1 + 16 + 32 + 16);
+        let decoded = Param::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.get::<KeyExchangeParam>(), Some(&param));
+        assert!(KeyExchangeParam::decode(&[2; 20]).is_err());
+    }
+
+    #[test]
+    fn test_modes_agree() {
+        exchange(
+            &KeyExchange::pre_shared(*b"rig"),
+            &KeyExchange::pre_shared(*b"rig"),
+        )
+        .unwrap();
+        exchange(&KeyExchange::x25519(), &KeyExchange::x25519()).unwrap();
+        let authenticated = KeyExchange::x25519().with_psk(*b"rig");
+        exchange(&authenticated, &authenticated.clone()).unwrap();
+
+        assert!(matches!(
+            exchange(
+                &KeyExchange::pre_shared(*b"rig"),
+                &KeyExchange::pre_shared(*b"other")
+            ),
+            Err(IrsError::KeyExchange(_))
+        ));
+        assert!(matches!(
+            exchange(&KeyExchange::x25519(), &KeyExchange::pre_shared(*b"rig")),
+            Err(IrsError::ConnectRefused(REFUSED))
+        ));
+    }
+
+    #[test]
+    fn test_replay_and_binding() {
+        let client = KeyExchange::pre_shared(*b"rig");
+        let board = KeyExchange::pre_shared(*b"rig");
+        let offer = client.offer().unwrap();
+        let request = carrying(offer.param.clone());
+        let (reply, _) = board.respond(&request, &binding()).unwrap();
+        assert!(matches!(
+            board.clone().respond(&request, &binding()),
+            Err("replayed nonce")
+        ));
+        // the board settled on another client ID than the client assumes
+        let other = Binding {
+            client_id: 8,
+            ..binding()
+        };
+        assert!(matches!(
+            offer.finish(&carrying(reply), &other),
+            Err(IrsError::KeyExchange("confirmation tag mismatch"))
+        ));
+    }
+
+    #[test]
+    fn test_directional_keys() {
+        let psk = KeyExchange::pre_shared(*b"rig");
+        let (client, board) = exchange(&psk, &psk.clone()).unwrap();
+        let sealed = |sender, cipher| {
+            let mut msg = Msg::new().with_sender(sender);
+            msg.add_param(Param::of(data::Param470));
+            msg.to_bytes_sealed(cipher).unwrap()
+        };
+        let open =
+            |bytes: &[u8], cipher| Msg::from_bytes_sealed(bytes, ParamRegistry::global(), cipher);
+
+        let request = sealed(binding().client, &client);
+        let reply = sealed(binding().board, &board);
+        // reflected back, a frame does not open at the end that sealed it
+        for (bytes, cipher) in [(&request, &client), (&reply, &board)] {
+            assert!(matches!(
+                open(bytes, cipher),
+                Err(IrsError::Unauthenticated)
+            ));
+        }
+        open(&request, &board).unwrap();
+        open(&reply, &client).unwrap();
+    }
+
+    #[test]
+    fn test_same_device_refused() {
+        let client = KeyExchange::x25519();
+        let offer = client.offer().unwrap();
+        let request = carrying(offer.param.clone());
+        let (reply, _) = KeyExchange::x25519().respond(&request, &binding()).unwrap();
+        let looped = Binding {
+            board: binding().client,
+            ..binding()
+        };
+        assert!(matches!(
+            KeyExchange::x25519().respond(&request, &looped),
+            Err("client and board are the same device")
+        ));
+        assert!(matches!(
+            offer.finish(&carrying(reply), &looped),
+            Err(IrsError::KeyExchange(
+                "client and board are the same device"
+            ))
+        ));
+    }
+}
@@ -0,0 +1,377 @@
+//! The parts of a client session that do no I/O: addressing frames, the
+//! client side of the connect handshake, sorting incoming frames and the
+//! keep-alive timers. [`Session`](super::Session) and
+//! [`IrsClient`](crate::async_client::IrsClient) drive them over their own
+//! transports.
+
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+use super::ConnectMode;
+#[cfg(feature = "key-exchange")]
+use super::key_exchange::{Binding, KeyExchange, Offer};
+use crate::{
+    error::IrsError,
+    msg::{
+        Msg,
+        cipher::SessionCipher,
+        header::{DeviceCode, MsgType},
+        params::{Param, ParamPayload},
+    },
+};
+
+/// Client ID and device codes stamped on the frames of a session.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Addressing {
+    pub client_id: u32,
+    /// Our own device code.
+    pub sender: DeviceCode,
+    /// The peer's device code.
+    pub receiver: DeviceCode,
+}
+
+impl Addressing {
+    /// An empty frame of `msg_type`, addressed to the peer.
+    pub fn frame(&self, msg_type: MsgType) -> Msg {
+        let mut msg = Msg::new();
+        msg.set_message_type(msg_type);
+        self.stamp(&mut msg);
+        msg
+    }
+
+    pub fn stamp(&self, msg: &mut Msg) {
+        let vh = msg.var_header_mut();
+        vh.client_id = Some(self.client_id);
+        vh.sender = Some(self.sender);
+        vh.receiver = Some(self.receiver);
+    }
+
+    /// What a frame received on a connected session means, given the
+    /// `heartbeat` the session uses, if any.
+    pub fn classify(&self, msg: Msg, heartbeat: Option<&Heartbeat>) -> Inbound {
+        match msg.get_message_type() {
+            MsgType::Data => {
+                let client_id = msg.var_header().client_id;
+                if client_id != Some(self.client_id) {
+                    debug!("dropping data for client {client_id:?}");
+                    Inbound::Foreign
+                } else if heartbeat.is_some_and(|h| h.matches(&msg)) {
+                    Inbound::Heartbeat
+                } else {
+                    Inbound::Data(msg)
+                }
+            }
+            MsgType::DisConnect | MsgType::DisConnectExtended => {
+                debug!("peer disconnected");
+                Inbound::Disconnect
+            }
+            other => Inbound::Unexpected(other),
+        }
+    }
+}
+
+/// A frame received on a connected session, sorted.
+pub(crate) enum Inbound {
+    Data(Msg),
+    Heartbeat,
+    /// Data for another client ID.
+    Foreign,
+    Disconnect,
+    /// A frame of a type that has no place in a connected session.
+    Unexpected(MsgType),
+}
+
+/// What a session sends after half the keep-alive interval without sending
+/// anything: a Data frame carrying parameter `param_id` with `data` and
+/// nothing else. Received frames like that are consumed as heartbeats.
+///
+/// DGE-RLM-0069 defines no keep-alive frame that we know of, so there is no
+/// default. Without a heartbeat, keep-alive only watches the peer, and the
+/// application has to send often enough to keep the peer's timer happy.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Heartbeat {
+    pub param_id: u16,
+    pub data: Vec<u8>,
+}
+
+impl Heartbeat {
+    pub fn new(param_id: u16, data: impl Into<Vec<u8>>) -> Self {
+        Heartbeat {
+            param_id,
+            data: data.into(),
+        }
+    }
+
+    /// The heartbeat frame, addressed to the peer.
+    pub(crate) fn frame(&self, addressing: &Addressing) -> Msg {
+        let mut msg = addressing.frame(MsgType::Data);
+        let payload = ParamPayload::raw(self.param_id, self.data.clone());
+        msg.add_param(Param::new(self.param_id, payload));
+        msg
+    }
+
+    /// Whether `msg` is a Data frame carrying this heartbeat's parameter and
+    /// no other.
+    pub fn matches(&self, msg: &Msg) -> bool {
+        let payload = msg.payload();
+        msg.get_message_type() == MsgType::Data
+            && payload.encrypted.is_empty()
+            && matches!(payload.get_params().as_slice(), [param] if param.id == self.param_id)
+    }
+}
+
+/// Client side of the connect handshake, between sending the connect
+/// request and checking the ack.
+pub(crate) struct Connect {
+    mode: ConnectMode,
+    accept_code: u8,
+    #[cfg(feature = "key-exchange")]
+    offer: Option<Offer>,
+}
+
+impl Connect {
+    /// Start a handshake: the connect request to send, proposing
+    /// `keep_alive` and offering a key exchange if there is one.
+    pub fn start(
+        addressing: &Addressing,
+        mode: ConnectMode,
+        accept_code: u8,
+        keep_alive: Duration,
+        #[cfg(feature = "key-exchange")] key_exchange: Option<&KeyExchange>,
+    ) -> Result<(Self, Msg), IrsError> {
+        let mut request = addressing.frame(mode.connect_type());
+        request
+            .var_header_mut()
+            .set_keepalive(keep_alive.as_secs() as u16);
+        #[cfg(feature = "key-exchange")]
+        let offer = match key_exchange {
+            Some(_) if mode != ConnectMode::Extended => {
+                return Err(IrsError::KeyExchange("needs an extended connect"));
+            }
+            Some(kx) => {
+                let offer = kx.offer()?;
+                request.add_param(Param::of(offer.param.clone()));
+                Some(offer)
+            }
+            None => None,
+        };
+        let connect = Connect {
+            mode,
+            accept_code,
+            #[cfg(feature = "key-exchange")]
+            offer,
+        };
+        Ok((connect, request))
+    }
+
+    /// Check the board's `ack` and adopt the client ID it settled on.
+    /// Returns the cipher agreed on, if a key exchange was offered.
+    pub fn finish(
+        self,
+        ack: &Msg,
+        addressing: &mut Addressing,
+    ) -> Result<Option<SessionCipher>, IrsError> {
+        if ack.get_message_type() != self.mode.ack_type() {
+            return Err(IrsError::UnexpectedMsg(ack.get_message_type()));
+        }
+        let vh = ack.var_header();
+        let code = vh.connect_return_code.unwrap_or_default();
+        if code != self.accept_code {
+            debug!("connect refused with code {code:#04x}");
+            return Err(IrsError::ConnectRefused(code));
+        }
+        if let (ConnectMode::Extended, Some(client_id)) = (self.mode, vh.client_id) {
+            // the extended ack carries the client ID the board settled on
+            addressing.client_id = client_id;
+        }
+        #[cfg(feature = "key-exchange")]
+        if let Some(offer) = self.offer {
+            let binding = Binding {
+                client_id: addressing.client_id,
+                client: addressing.sender,
+                board: addressing.receiver,
+            };
+            return Ok(Some(offer.finish(ack, &binding)?));
+        }
+        Ok(None)
+    }
+}
+
+/// Keep-alive timers: a heartbeat is due after half the interval without
+/// sending anything, and the peer is given up on once a whole interval
+/// passes without receiving anything. A zero interval disables both.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct KeepAlive {
+    interval: Duration,
+    last_rx: Instant,
+    last_tx: Instant,
+}
+
+/// What the keep-alive timers call for.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Due {
+    Nothing,
+    Heartbeat,
+    Expired,
+}
+
+impl KeepAlive {
+    pub fn new(interval: Duration, now: Instant) -> Self {
+        KeepAlive {
+            interval,
+            last_rx: now,
+            last_tx: now,
+        }
+    }
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+    /// Restart both timers, e.g. after switching clocks.
+    pub fn reset(&mut self, now: Instant) {
+        self.last_rx = now;
+        self.last_tx = now;
+    }
+    pub fn received(&mut self, now: Instant) {
+        self.last_rx = now;
+    }
+    pub fn sent(&mut self, now: Instant) {
+        self.last_tx = now;
+    }
+    /// How often to check [`KeepAlive::due`], `None` if disabled.
+    pub fn check_every(&self) -> Option<Duration> {
+        (!self.interval.is_zero()).then_some(self.interval / 4)
+    }
+    pub fn due(&self, now: Instant) -> Due {
+        if self.interval.is_zero() {
+            Due::Nothing
+        } else if now.duration_since(self.last_rx) >= self.interval {
+            Due::Expired
+        } else if now.duration_since(self.last_tx) >= self.interval / 2 {
+            Due::Heartbeat
+        } else {
+            Due::Nothing
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::params::data::Param470;
+
+    fn addressing() -> Addressing {
+        Addressing {
+            client_id: 7,
+            sender: DeviceCode::MobileApp,
+            receiver: DeviceCode::MowerMainBoardApplicationSw,
+        }
+    }
+
+    fn ack(code: u8, client_id: u32) -> Msg {
+        let mut ack = Msg::new();
+        ack.set_message_type(MsgType::ConnectExtendedAck);
+        ack.set_client_id(client_id);
+        ack.var_header_mut().connect_return_code = Some(code);
+        ack
+    }
+
+    fn start() -> Connect {
+        let (connect, request) = Connect::start(
+            &addressing(),
+            ConnectMode::Extended,
+            0x09,
+            Duration::from_secs(10),
+            #[cfg(feature = "key-exchange")]
+            None,
+        )
+        .unwrap();
+        assert_eq!(request.get_message_type(), MsgType::ConnectExtended);
+        assert_eq!(request.var_header().keepalive(), Some(10));
+        assert_eq!(request.sender(), Some(DeviceCode::MobileApp));
+        connect
+    }
+
+    #[test]
+    fn test_connect() {
+        let mut accepted = addressing();
+        assert!(
+            start()
+                .finish(&ack(0x09, 3), &mut accepted)
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(accepted.client_id, 3);
+
+        let mut refused = addressing();
+        assert!(matches!(
+            start().finish(&ack(0x02, 3), &mut refused),
+            Err(IrsError::ConnectRefused(0x02))
+        ));
+        assert_eq!(refused.client_id, 7);
+        let mut data = ack(0x09, 3);
+        data.set_message_type(MsgType::Data);
+        assert!(matches!(
+            start().finish(&data, &mut refused),
+            Err(IrsError::UnexpectedMsg(MsgType::Data))
+        ));
+    }
+
+    #[test]
+    fn test_classify() {
+        let addressing = addressing();
+        let heartbeat = Heartbeat::new(0x7fff, []);
+        let data = |client_id| {
+            let mut msg = addressing.frame(MsgType::Data);
+            msg.set_client_id(client_id);
+            msg.add_param(Param::of(Param470));
+            msg
+        };
+        let classify = |msg| addressing.classify(msg, Some(&heartbeat));
+        assert!(matches!(classify(data(7)), Inbound::Data(_)));
+        assert!(matches!(classify(data(8)), Inbound::Foreign));
+        assert!(matches!(
+            classify(heartbeat.frame(&addressing)),
+            Inbound::Heartbeat
+        ));
+        // without a heartbeat configured, it is data like any other
+        assert!(matches!(
+            addressing.classify(heartbeat.frame(&addressing), None),
+            Inbound::Data(_)
+        ));
+        // and an empty Data frame never is a heartbeat
+        assert!(matches!(
+            classify(addressing.frame(MsgType::Data)),
+            Inbound::Data(_)
+        ));
+        let disconnect = addressing.frame(MsgType::DisConnectExtended);
+        assert!(matches!(classify(disconnect), Inbound::Disconnect));
+        let ack = addressing.frame(MsgType::ConnectAck);
+        assert!(matches!(
+            classify(ack),
+            Inbound::Unexpected(MsgType::ConnectAck)
+        ));
+    }
+
+    #[test]
+    fn test_keep_alive_due() {
+        let start = Instant::now();
+        let secs = |n| start + Duration::from_secs(n);
+        let mut keep_alive = KeepAlive::new(Duration::from_secs(10), start);
+        assert_eq!(keep_alive.check_every(), Some(Duration::from_millis(2500)));
+        assert_eq!(keep_alive.due(secs(4)), Due::Nothing);
+        assert_eq!(keep_alive.due(secs(5)), Due::Heartbeat);
+        keep_alive.sent(secs(5));
+        assert_eq!(keep_alive.due(secs(9)), Due::Nothing);
+        assert_eq!(keep_alive.due(secs(10)), Due::Expired);
+        keep_alive.received(secs(10));
+        assert_eq!(keep_alive.due(secs(10)), Due::Heartbeat);
+
+        keep_alive.set_interval(Duration::ZERO);
+        assert_eq!(keep_alive.check_every(), None);
+        assert_eq!(keep_alive.due(secs(100)), Due::Nothing);
+    }
+}
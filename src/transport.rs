@@ -0,0 +1,142 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tracing::debug;
+
+use crate::{
+    error::IrsError,
+    msg::{Msg, cipher::SessionCipher, frame::FrameDecoder, params::ParamRegistry},
+};
+
+pub mod memory;
+#[cfg(feature = "serial")]
+pub mod serial;
+pub mod tcp;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+const READ_CHUNK: usize = 256;
+
+/// Something that carries whole IRS frames.
+pub trait Transport {
+    fn send(&mut self, msg: &Msg) -> Result<(), IrsError>;
+    /// Wait at most `timeout` for the next frame.
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<Msg, IrsError>;
+    /// Seal the payloads sent and open those received with `cipher` from
+    /// here on, or stop doing so with `None`.
+    fn set_cipher(&mut self, cipher: Option<Arc<SessionCipher>>);
+}
+
+/// A byte link whose reads can be bounded in time, e.g. a serial port.
+pub trait Link: Read + Write {
+    fn set_read_timeout(&mut self, timeout: Duration) -> std::io::Result<()>;
+}
+
+/// Frames [`Msg`]s over a [`Link`] with a [`FrameDecoder`], so noise and
+/// arbitrary read chunking on the link are tolerated.
+pub struct Framed<L> {
+    link: L,
+    decoder: FrameDecoder,
+    cipher: Option<Arc<SessionCipher>>,
+    timeout: Duration,
+}
+
+impl<L: Link> Framed<L> {
+    pub fn new(link: L) -> Self {
+        Framed {
+            link,
+            decoder: FrameDecoder::new(),
+            cipher: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+    /// Timeout used by [`Framed::recv`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// Decode parameters with `registry` instead of the global one.
+    pub fn with_registry(mut self, registry: ParamRegistry) -> Self {
+        self.decoder = std::mem::take(&mut self.decoder).with_registry(registry);
+        self
+    }
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+    /// See [`Transport::set_cipher`].
+    pub fn set_cipher(&mut self, cipher: Option<Arc<SessionCipher>>) {
+        self.decoder.set_cipher(cipher.clone());
+        self.cipher = cipher;
+    }
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+    /// Decoder state, e.g. its dropped byte and bad frame counters.
+    pub fn decoder(&self) -> &FrameDecoder {
+        &self.decoder
+    }
+    pub fn get_ref(&self) -> &L {
+        &self.link
+    }
+    pub fn get_mut(&mut self) -> &mut L {
+        &mut self.link
+    }
+    pub fn into_inner(self) -> L {
+        self.link
+    }
+
+    pub fn send(&mut self, msg: &Msg) -> Result<(), IrsError> {
+        let bytes = match &self.cipher {
+            Some(cipher) => msg.to_bytes_sealed(cipher)?,
+            None => msg.to_bytes()?,
+        };
+        debug!("send {} bytes", bytes.len());
+        self.link.write_all(&bytes)?;
+        self.link.flush()?;
+        Ok(())
+    }
+
+    /// Wait for the next frame, at most the configured timeout.
+    pub fn recv(&mut self) -> Result<Msg, IrsError> {
+        self.recv_timeout(self.timeout)
+    }
+
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Msg, IrsError> {
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0u8; READ_CHUNK];
+        loop {
+            if let Some(msg) = self.decoder.next_msg() {
+                return Ok(msg);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(IrsError::Timeout);
+            }
+            self.link.set_read_timeout(deadline - now)?;
+            match self.link.read(&mut buf) {
+                Ok(0) => return Err(IrsError::Closed),
+                Ok(n) => self.decoder.push(&buf[..n]),
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                    ) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl<L: Link> Transport for Framed<L> {
+    fn send(&mut self, msg: &Msg) -> Result<(), IrsError> {
+        Framed::send(self, msg)
+    }
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<Msg, IrsError> {
+        Framed::recv_timeout(self, timeout)
+    }
+    fn set_cipher(&mut self, cipher: Option<Arc<SessionCipher>>) {
+        Framed::set_cipher(self, cipher)
+    }
+}
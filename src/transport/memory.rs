@@ -0,0 +1,71 @@
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind, Read, Write},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    time::Duration,
+};
+
+use super::{Framed, Link};
+
+/// One end of an in-memory byte pipe, for exercising sessions without I/O.
+pub struct MemoryLink {
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+    pending: VecDeque<u8>,
+    timeout: Duration,
+}
+
+/// Two connected [`MemoryLink`]s: bytes written to one are read from the other.
+pub fn pipe() -> (MemoryLink, MemoryLink) {
+    let (a_tx, b_rx) = mpsc::channel();
+    let (b_tx, a_rx) = mpsc::channel();
+    let link = |tx, rx| MemoryLink {
+        tx,
+        rx,
+        pending: VecDeque::new(),
+        timeout: Duration::from_secs(1),
+    };
+    (link(a_tx, a_rx), link(b_tx, b_rx))
+}
+
+/// [`pipe`] with a [`Framed`] on each end.
+pub fn pair() -> (Framed<MemoryLink>, Framed<MemoryLink>) {
+    let (a, b) = pipe();
+    (Framed::new(a), Framed::new(b))
+}
+
+impl Read for MemoryLink {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.rx.recv_timeout(self.timeout) {
+                Ok(chunk) => self.pending.extend(chunk),
+                Err(RecvTimeoutError::Timeout) => return Err(ErrorKind::TimedOut.into()),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.pending.len());
+        for (dst, src) in buf.iter_mut().zip(self.pending.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for MemoryLink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tx
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::from(ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Link for MemoryLink {
+    fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+}
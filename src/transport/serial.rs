@@ -0,0 +1,187 @@
+use std::{sync::Arc, time::Duration};
+
+use serialport::SerialPort;
+pub use serialport::{DataBits, FlowControl, Parity, StopBits};
+
+use super::{Framed, Link, Transport};
+use crate::{
+    error::IrsError,
+    msg::{Msg, cipher::SessionCipher},
+};
+
+/// Settings for [`SerialTransport::open`]. Defaults to 115200 8N1 without
+/// flow control, as used by the mower main board UART.
+#[derive(Debug, Clone)]
+pub struct SerialConfig {
+    pub path: String,
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+    pub timeout: Duration,
+}
+
+impl SerialConfig {
+    pub fn new(path: impl Into<String>) -> Self {
+        SerialConfig {
+            path: path.into(),
+            baud_rate: 115_200,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            timeout: Duration::from_secs(1),
+        }
+    }
+    pub fn with_baud_rate(mut self, v: u32) -> Self {
+        self.baud_rate = v;
+        self
+    }
+    pub fn with_data_bits(mut self, v: DataBits) -> Self {
+        self.data_bits = v;
+        self
+    }
+    pub fn with_parity(mut self, v: Parity) -> Self {
+        self.parity = v;
+        self
+    }
+    pub fn with_stop_bits(mut self, v: StopBits) -> Self {
+        self.stop_bits = v;
+        self
+    }
+    pub fn with_flow_control(mut self, v: FlowControl) -> Self {
+        self.flow_control = v;
+        self
+    }
+    pub fn with_timeout(mut self, v: Duration) -> Self {
+        self.timeout = v;
+        self
+    }
+}
+
+impl Link for Box<dyn SerialPort> {
+    fn set_read_timeout(&mut self, timeout: Duration) -> std::io::Result<()> {
+        Ok(self.set_timeout(timeout)?)
+    }
+}
+
+/// IRS frames over a UART.
+pub struct SerialTransport {
+    framed: Framed<Box<dyn SerialPort>>,
+}
+
+impl SerialTransport {
+    pub fn open(config: &SerialConfig) -> Result<Self, IrsError> {
+        let port = serialport::new(&config.path, config.baud_rate)
+            .data_bits(config.data_bits)
+            .parity(config.parity)
+            .stop_bits(config.stop_bits)
+            .flow_control(config.flow_control)
+            .timeout(config.timeout)
+            .open()
+            .map_err(std::io::Error::from)?;
+        Ok(Self::from_port(port).with_timeout(config.timeout))
+    }
+
+    /// Wrap a port that was opened elsewhere.
+    pub fn from_port(port: Box<dyn SerialPort>) -> Self {
+        SerialTransport {
+            framed: Framed::new(port),
+        }
+    }
+
+    /// A connected pseudo-terminal pair, handy as a stand-in for the board.
+    #[cfg(unix)]
+    pub fn pair() -> Result<(Self, Self), IrsError> {
+        let (master, slave) = serialport::TTYPort::pair().map_err(std::io::Error::from)?;
+        Ok((
+            Self::from_port(Box::new(master)),
+            Self::from_port(Box::new(slave)),
+        ))
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.framed.set_timeout(timeout);
+        self
+    }
+    pub fn framed(&self) -> &Framed<Box<dyn SerialPort>> {
+        &self.framed
+    }
+    pub fn port_mut(&mut self) -> &mut Box<dyn SerialPort> {
+        self.framed.get_mut()
+    }
+
+    pub fn send(&mut self, msg: &Msg) -> Result<(), IrsError> {
+        self.framed.send(msg)
+    }
+    pub fn recv(&mut self) -> Result<Msg, IrsError> {
+        self.framed.recv()
+    }
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Msg, IrsError> {
+        self.framed.recv_timeout(timeout)
+    }
+}
+
+impl Transport for SerialTransport {
+    fn send(&mut self, msg: &Msg) -> Result<(), IrsError> {
+        self.framed.send(msg)
+    }
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<Msg, IrsError> {
+        self.framed.recv_timeout(timeout)
+    }
+    fn set_cipher(&mut self, cipher: Option<Arc<SessionCipher>>) {
+        self.framed.set_cipher(cipher)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::msg::params::{Param, data};
+
+    fn request(msg_id: u8) -> Msg {
+        let mut msg = Msg::new();
+        msg.set_msg_id(msg_id);
+        msg.add_param(Param::of(data::Param470));
+        msg
+    }
+
+    #[test]
+    fn test_pty_send_recv() {
+        let (mut board, mut pc) = SerialTransport::pair().unwrap();
+        pc.send(&request(1)).unwrap();
+        pc.send(&request(2)).unwrap();
+        assert_eq!(board.recv().unwrap().get_msg_id(), 1);
+        assert_eq!(board.recv().unwrap().get_msg_id(), 2);
+        board.send(&request(3)).unwrap();
+        assert_eq!(pc.recv().unwrap().get_msg_id(), 3);
+    }
+
+    #[test]
+    fn test_pty_noise_and_split_writes() {
+        let (mut board, mut pc) = SerialTransport::pair().unwrap();
+        let bytes = request(7).to_bytes().unwrap();
+        let port = pc.port_mut();
+        port.write_all(&[0x55, 0x01, 0xaa]).unwrap();
+        port.write_all(&bytes[..5]).unwrap();
+        port.flush().unwrap();
+        port.write_all(&bytes[5..]).unwrap();
+        port.flush().unwrap();
+        assert_eq!(board.recv().unwrap().get_msg_id(), 7);
+        assert_eq!(board.framed().decoder().dropped_bytes(), 3);
+    }
+
+    #[test]
+    fn test_pty_timeout() {
+        let (mut board, _pc) = SerialTransport::pair().unwrap();
+        let start = std::time::Instant::now();
+        assert!(matches!(
+            board.recv_timeout(Duration::from_millis(50)),
+            Err(IrsError::Timeout)
+        ));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+}
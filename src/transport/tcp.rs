@@ -0,0 +1,238 @@
+use std::{
+    io,
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::Arc,
+    time::Duration,
+};
+
+use tracing::{debug, warn};
+
+use super::{Framed, Link, Transport};
+use crate::{
+    error::IrsError,
+    msg::{Msg, cipher::SessionCipher},
+};
+
+impl Link for TcpStream {
+    fn set_read_timeout(&mut self, timeout: Duration) -> std::io::Result<()> {
+        // a zero timeout means "block forever" to the socket API
+        TcpStream::set_read_timeout(self, Some(timeout.max(Duration::from_millis(1))))
+    }
+}
+
+/// IRS frames over a TCP connection, e.g. between backend and app.
+pub struct TcpTransport {
+    framed: Framed<TcpStream>,
+}
+
+impl TcpTransport {
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self, IrsError> {
+        let stream = TcpStream::connect(addr)?;
+        Self::from_stream(stream)
+    }
+
+    pub fn connect_timeout(addr: &SocketAddr, timeout: Duration) -> Result<Self, IrsError> {
+        let stream = TcpStream::connect_timeout(addr, timeout)?;
+        Self::from_stream(stream)
+    }
+
+    pub fn from_stream(stream: TcpStream) -> Result<Self, IrsError> {
+        stream.set_nodelay(true)?;
+        Ok(TcpTransport {
+            framed: Framed::new(stream),
+        })
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.framed.set_timeout(timeout);
+        self
+    }
+    pub fn peer_addr(&self) -> Result<SocketAddr, IrsError> {
+        Ok(self.framed.get_ref().peer_addr()?)
+    }
+    pub fn framed(&self) -> &Framed<TcpStream> {
+        &self.framed
+    }
+
+    pub fn send(&mut self, msg: &Msg) -> Result<(), IrsError> {
+        self.framed.send(msg)
+    }
+    pub fn recv(&mut self) -> Result<Msg, IrsError> {
+        self.framed.recv()
+    }
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Msg, IrsError> {
+        self.framed.recv_timeout(timeout)
+    }
+    /// Close both directions; the peer's next `recv` fails with [`IrsError::Closed`].
+    pub fn shutdown(&self) -> Result<(), IrsError> {
+        Ok(self.framed.get_ref().shutdown(std::net::Shutdown::Both)?)
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send(&mut self, msg: &Msg) -> Result<(), IrsError> {
+        self.framed.send(msg)
+    }
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<Msg, IrsError> {
+        self.framed.recv_timeout(timeout)
+    }
+    fn set_cipher(&mut self, cipher: Option<Arc<SessionCipher>>) {
+        self.framed.set_cipher(cipher)
+    }
+}
+
+/// How long [`TcpServer::serve`] waits after an accept failed for lack of
+/// resources, e.g. file descriptors, before trying again.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Accepts IRS connections, one [`TcpTransport`] per peer.
+pub struct TcpServer {
+    listener: TcpListener,
+}
+
+impl TcpServer {
+    pub fn bind(addr: impl ToSocketAddrs) -> Result<Self, IrsError> {
+        Ok(TcpServer {
+            listener: TcpListener::bind(addr)?,
+        })
+    }
+    pub fn local_addr(&self) -> Result<SocketAddr, IrsError> {
+        Ok(self.listener.local_addr()?)
+    }
+    pub fn accept(&self) -> Result<(TcpTransport, SocketAddr), IrsError> {
+        let (stream, addr) = self.listener.accept()?;
+        debug!("accepted {addr}");
+        Ok((TcpTransport::from_stream(stream)?, addr))
+    }
+    /// Accept connections forever, handing each one to `handler` on its own
+    /// thread. A failed accept is logged and skipped; this only returns if
+    /// the listener itself is unusable.
+    pub fn serve<F>(&self, handler: F) -> Result<(), IrsError>
+    where
+        F: Fn(TcpTransport, SocketAddr) + Send + Clone + 'static,
+    {
+        loop {
+            let (stream, addr) = match self.listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) => match retry_after(&e) {
+                    Some(Duration::ZERO) => {
+                        debug!("accept failed: {e}");
+                        continue;
+                    }
+                    Some(backoff) => {
+                        warn!("accept failed, retrying in {backoff:?}: {e}");
+                        std::thread::sleep(backoff);
+                        continue;
+                    }
+                    None => return Err(e.into()),
+                },
+            };
+            debug!("accepted {addr}");
+            // a peer that is gone already can fail the socket setup
+            let transport = match TcpTransport::from_stream(stream) {
+                Ok(transport) => transport,
+                Err(e) => {
+                    debug!("dropping {addr}: {e}");
+                    continue;
+                }
+            };
+            let handler = handler.clone();
+            std::thread::spawn(move || handler(transport, addr));
+        }
+    }
+}
+
+/// When to accept again after `e`, `None` if the listener is unusable.
+fn retry_after(e: &io::Error) -> Option<Duration> {
+    match e.kind() {
+        io::ErrorKind::InvalidInput | io::ErrorKind::Unsupported => None,
+        // the connection went away before or while it was set up
+        io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::Interrupted
+        | io::ErrorKind::WouldBlock
+        | io::ErrorKind::TimedOut
+        | io::ErrorKind::NotConnected => Some(Duration::ZERO),
+        // out of file descriptors, buffers or memory: give peers a moment to
+        // close theirs
+        _ => Some(ACCEPT_BACKOFF),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::msg::params::{Param, data};
+
+    fn request(msg_id: u8) -> Msg {
+        let mut msg = Msg::new();
+        msg.set_msg_id(msg_id);
+        msg.add_param(Param::of(data::Param470));
+        msg
+    }
+
+    #[test]
+    fn test_loopback_echo_many_clients() {
+        let server = TcpServer::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || {
+            server
+                .serve(|mut transport, _| {
+                    while let Ok(msg) = transport.recv() {
+                        let mut reply = Msg::new();
+                        reply.set_msg_id(msg.get_msg_id().wrapping_add(100));
+                        transport.send(&reply).unwrap();
+                    }
+                })
+                .unwrap();
+        });
+
+        let clients: Vec<_> = (0..4u8)
+            .map(|i| {
+                thread::spawn(move || {
+                    let mut client = TcpTransport::connect(addr).unwrap();
+                    for n in 0..3u8 {
+                        let msg_id = i * 10 + n;
+                        client.send(&request(msg_id)).unwrap();
+                        assert_eq!(client.recv().unwrap().get_msg_id(), msg_id + 100);
+                    }
+                })
+            })
+            .collect();
+        for client in clients {
+            client.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_accept_errors() {
+        let retry = |kind| retry_after(&io::Error::from(kind));
+        assert_eq!(
+            retry(io::ErrorKind::ConnectionAborted),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(retry(io::ErrorKind::OutOfMemory), Some(ACCEPT_BACKOFF));
+        // EMFILE has no ErrorKind of its own
+        #[cfg(unix)]
+        assert_eq!(
+            retry_after(&io::Error::from_raw_os_error(24)),
+            Some(ACCEPT_BACKOFF)
+        );
+        assert_eq!(retry(io::ErrorKind::InvalidInput), None);
+    }
+
+    #[test]
+    fn test_peer_close_and_timeout() {
+        let server = TcpServer::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpTransport::connect(server.local_addr().unwrap()).unwrap();
+        let (peer, _) = server.accept().unwrap();
+        assert!(matches!(
+            client.recv_timeout(Duration::from_millis(20)),
+            Err(IrsError::Timeout)
+        ));
+        peer.shutdown().unwrap();
+        assert!(matches!(client.recv(), Err(IrsError::Closed)));
+    }
+}
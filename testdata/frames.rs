@@ -0,0 +1,22 @@
+// Reference frames shared by the tests of irs-rs and irs-tools, pulled in
+// with `include!`.
+//
+// They were produced by irs-rs's own encoder, not captured from a mower, so
+// they pin the encoding against regressions but cannot show that the CRC
+// scope or byte order matches the firmware. Replace them with real captures
+// once some are available.
+
+/// Data frame, client 1, PC UART -> mower main board, msg_id 5, one 470 request.
+#[allow(dead_code)] // not every including test module uses both
+const ENCODED_470: [u8; 22] = [
+    0x01, 0x02, 0x03, 0x09, 0x00, 0x84, 0x78, 0x01, 0x00, 0x00, 0x00, 0x4f, 0x4d, 0x05, 0x04, 0x00,
+    0xd6, 0x01, 0x00, 0x00, 0x3d, 0x0c,
+];
+
+/// Data frame, client 1, mower main board -> PC UART, msg_id 6, 471 response
+/// with default height 30 and current height 45.
+#[allow(dead_code)]
+const ENCODED_471: [u8; 26] = [
+    0x01, 0x02, 0x03, 0x0d, 0x00, 0x05, 0x2a, 0x01, 0x00, 0x00, 0x00, 0x4d, 0x4f, 0x06, 0x08, 0x00,
+    0xd7, 0x01, 0x04, 0x00, 0x00, 0x1e, 0x2d, 0x00, 0xbf, 0xca,
+];